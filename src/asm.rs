@@ -0,0 +1,664 @@
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt;
+
+const REGS: [&str; 8] = ["B", "C", "D", "E", "H", "L", "M", "A"];
+const PAIRS: [&str; 4] = ["B", "D", "H", "SP"];
+const STACK_PAIRS: [&str; 4] = ["B", "D", "H", "PSW"];
+const ALU: [&str; 8] = ["ADD", "ADC", "SUB", "SBB", "ANA", "XRA", "ORA", "CMP"];
+const ALU_IMM: [&str; 8] = ["ADI", "ACI", "SUI", "SBI", "ANI", "XRI", "ORI", "CPI"];
+const CONDITIONS: [&str; 8] = ["NZ", "Z", "NC", "C", "PO", "PE", "P", "M"];
+const DIRECTIVES: [&str; 7] = ["ORG", "DB", "DW", "DS", "EQU", "SET", "END"];
+
+/// Assembled program: the bytes from the lowest to the highest address
+/// written, with gaps left by ORG or DS filled with zeros
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Program {
+  pub origin: u16,
+  pub bytes: Vec<u8>,
+  pub symbols: BTreeMap<String, u16>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+  /// 1-based source line
+  pub line: usize,
+  pub message: String,
+}
+
+impl fmt::Display for AsmError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "line {}: {}", self.line, self.message)
+  }
+}
+
+impl Error for AsmError {}
+
+/// Assemble Intel 8080 source
+///
+/// Supports labels (with or without a colon), ORG, DB, DW, DS, EQU, SET and
+/// END, and expressions with `+ - * / MOD SHL SHR NOT AND OR XOR HIGH LOW`,
+/// parentheses, `$` for the current address, character constants and
+/// numbers in decimal or with an `H`, `B`, `O`/`Q` or `D` suffix.
+pub fn assemble(source: &str) -> Result<Program, AsmError> {
+  let lines: Vec<Line> = source
+    .lines()
+    .enumerate()
+    .map(|(i, text)| Line::parse(text).map_err(|message| AsmError { line: i + 1, message }))
+    .collect::<Result<Vec<Line>, AsmError>>()?;
+
+  let mut assembler = Assembler { symbols: HashMap::new(), pc: 0, here: 0, output: BTreeMap::new(), final_pass: false };
+  assembler.pass(&lines)?;
+  assembler.final_pass = true;
+  assembler.output.clear();
+  assembler.pass(&lines)?;
+
+  let symbols = assembler.symbols.iter().map(|(name, &value)| (name.clone(), value)).collect();
+  let (origin, bytes) = match (assembler.output.keys().next(), assembler.output.keys().next_back()) {
+    (Some(&first), Some(&last)) => {
+      let bytes = (first..=last).map(|hblb| assembler.output.get(&hblb).copied().unwrap_or(0)).collect();
+      (first, bytes)
+    },
+    _ => (0, Vec::new()),
+  };
+  Ok(Program { origin, bytes, symbols })
+}
+
+/// A source line split into its fields
+struct Line {
+  label: Option<String>,
+  mnemonic: Option<String>,
+  operands: Vec<String>,
+}
+
+impl Line {
+  fn parse(text: &str) -> Result<Line, String> {
+    let text = strip_comment(text);
+    let starts_in_column = text.starts_with(|c: char| !c.is_whitespace());
+    let mut rest = text.trim();
+    let mut label = None;
+
+    let first = leading_word(rest);
+    if !first.is_empty() {
+      let after = rest[first.len()..].trim_start();
+      if let Some(after_colon) = after.strip_prefix(':') {
+        label = Some(first.to_uppercase());
+        rest = after_colon.trim_start();
+      } else if starts_in_column {
+        let next = leading_word(after).to_uppercase();
+        let upper = first.to_uppercase();
+        if next == "EQU" || next == "SET" || !is_mnemonic(&upper) {
+          label = Some(upper);
+          rest = after;
+        }
+      }
+    }
+
+    let word = leading_word(rest);
+    let mnemonic = if word.is_empty() {
+      if !rest.is_empty() {
+        return Err(format!("unexpected '{}'", rest));
+      }
+      None
+    } else {
+      Some(word.to_uppercase())
+    };
+    let operands = split_operands(rest[word.len()..].trim());
+    Ok(Line { label, mnemonic, operands })
+  }
+}
+
+struct Assembler {
+  symbols: HashMap<String, u16>,
+  pc: u16,
+  /// Address of the line being assembled, the value of `$`
+  here: u16,
+  output: BTreeMap<u16, u8>,
+  final_pass: bool,
+}
+
+impl Assembler {
+  fn pass(&mut self, lines: &[Line]) -> Result<(), AsmError> {
+    self.pc = 0;
+    for (i, line) in lines.iter().enumerate() {
+      let error = |message: String| AsmError { line: i + 1, message };
+      if self.line(line).map_err(error)? {
+        break;
+      }
+    }
+    Ok(())
+  }
+
+  /// Assemble one line, returns true at END
+  fn line(&mut self, line: &Line) -> Result<bool, String> {
+    let mnemonic = line.mnemonic.as_deref().unwrap_or("");
+    self.here = self.pc;
+    match mnemonic {
+      "EQU" | "SET" => {
+        let name = line.label.as_ref().ok_or(format!("{} without a name", mnemonic))?;
+        let value = self.eval(self.operand(line, 0)?)?;
+        if mnemonic == "EQU" && !self.final_pass && self.symbols.contains_key(name) {
+          return Err(format!("duplicate symbol {}", name));
+        }
+        self.symbols.insert(name.clone(), value as u16);
+        return Ok(false);
+      },
+      _ => if let Some(label) = &line.label {
+        if !self.final_pass && self.symbols.insert(label.clone(), self.pc).is_some() {
+          return Err(format!("duplicate symbol {}", label));
+        }
+      },
+    }
+
+    match mnemonic {
+      "" => (),
+      "END" => return Ok(true),
+      "ORG" => self.pc = self.eval_defined(self.operand(line, 0)?)? as u16,
+      "DS" => self.pc = self.pc.wrapping_add(self.eval_defined(self.operand(line, 0)?)? as u16),
+      "DB" => for operand in &line.operands {
+        match string_literal(operand) {
+          Some(text) if text.len() != 1 => for db in text.bytes() {
+            self.emit(db);
+          },
+          _ => {
+            let db = self.byte(operand)?;
+            self.emit(db);
+          },
+        }
+      },
+      "DW" => for operand in &line.operands {
+        let hblb = self.word(operand)?;
+        self.emit_word(hblb);
+      },
+      _ => self.instruction(mnemonic, line)?,
+    }
+    Ok(false)
+  }
+
+  fn instruction(&mut self, mnemonic: &str, line: &Line) -> Result<(), String> {
+    let expect = |count: usize| if line.operands.len() == count {
+      Ok(())
+    } else {
+      Err(format!("{} takes {} operand(s)", mnemonic, count))
+    };
+    let implied = [
+      ("NOP", 0x00), ("RLC", 0x07), ("RRC", 0x0F), ("RAL", 0x17), ("RAR", 0x1F), ("DAA", 0x27),
+      ("CMA", 0x2F), ("STC", 0x37), ("CMC", 0x3F), ("HLT", 0x76), ("RET", 0xC9), ("XTHL", 0xE3),
+      ("PCHL", 0xE9), ("XCHG", 0xEB), ("DI", 0xF3), ("SPHL", 0xF9), ("EI", 0xFB),
+    ];
+    if let Some(&(_, opcode)) = implied.iter().find(|(name, _)| *name == mnemonic) {
+      expect(0)?;
+      self.emit(opcode);
+      return Ok(());
+    }
+    if let Some(i) = ALU.iter().position(|name| *name == mnemonic) {
+      expect(1)?;
+      let s = self.reg(&line.operands[0])?;
+      self.emit(0x80 | (i as u8) << 3 | s);
+      return Ok(());
+    }
+    if let Some(i) = ALU_IMM.iter().position(|name| *name == mnemonic) {
+      expect(1)?;
+      let db = self.byte(&line.operands[0])?;
+      self.emit(0xC6 | (i as u8) << 3);
+      self.emit(db);
+      return Ok(());
+    }
+    if let Some((prefix, ccc)) = conditional(mnemonic) {
+      let (opcode, operands) = match prefix {
+        'J' => (0xC2, 1),
+        'C' => (0xC4, 1),
+        _ => (0xC0, 0),
+      };
+      expect(operands)?;
+      self.emit(opcode | ccc << 3);
+      if operands == 1 {
+        let hblb = self.word(&line.operands[0])?;
+        self.emit_word(hblb);
+      }
+      return Ok(());
+    }
+
+    match mnemonic {
+      "MOV" => {
+        expect(2)?;
+        let d = self.reg(&line.operands[0])?;
+        let s = self.reg(&line.operands[1])?;
+        if d == 6 && s == 6 {
+          return Err(String::from("MOV M,M is HLT"));
+        }
+        self.emit(0x40 | d << 3 | s);
+      },
+      "MVI" => {
+        expect(2)?;
+        let d = self.reg(&line.operands[0])?;
+        let db = self.byte(&line.operands[1])?;
+        self.emit(0x06 | d << 3);
+        self.emit(db);
+      },
+      "INR" | "DCR" => {
+        expect(1)?;
+        let d = self.reg(&line.operands[0])?;
+        self.emit(if mnemonic == "INR" { 0x04 } else { 0x05 } | d << 3);
+      },
+      "LXI" => {
+        expect(2)?;
+        let rp = self.pair(&line.operands[0], &PAIRS)?;
+        let hblb = self.word(&line.operands[1])?;
+        self.emit(0x01 | rp << 4);
+        self.emit_word(hblb);
+      },
+      "INX" | "DCX" | "DAD" => {
+        expect(1)?;
+        let rp = self.pair(&line.operands[0], &PAIRS)?;
+        let opcode = match mnemonic {
+          "INX" => 0x03,
+          "DCX" => 0x0B,
+          _ => 0x09,
+        };
+        self.emit(opcode | rp << 4);
+      },
+      "STAX" | "LDAX" => {
+        expect(1)?;
+        let rp = self.pair(&line.operands[0], &PAIRS[..2])?;
+        self.emit(if mnemonic == "STAX" { 0x02 } else { 0x0A } | rp << 4);
+      },
+      "PUSH" | "POP" => {
+        expect(1)?;
+        let rp = self.pair(&line.operands[0], &STACK_PAIRS)?;
+        self.emit(if mnemonic == "PUSH" { 0xC5 } else { 0xC1 } | rp << 4);
+      },
+      "JMP" | "CALL" | "LDA" | "STA" | "LHLD" | "SHLD" => {
+        expect(1)?;
+        let opcode = match mnemonic {
+          "JMP" => 0xC3,
+          "CALL" => 0xCD,
+          "LDA" => 0x3A,
+          "STA" => 0x32,
+          "LHLD" => 0x2A,
+          _ => 0x22,
+        };
+        let hblb = self.word(&line.operands[0])?;
+        self.emit(opcode);
+        self.emit_word(hblb);
+      },
+      "IN" | "OUT" => {
+        expect(1)?;
+        let port = self.byte(&line.operands[0])?;
+        self.emit(if mnemonic == "IN" { 0xDB } else { 0xD3 });
+        self.emit(port);
+      },
+      "RST" => {
+        expect(1)?;
+        let n = self.eval(&line.operands[0])?;
+        if !(0..8).contains(&n) {
+          return Err(format!("RST {} out of range", n));
+        }
+        self.emit(0xC7 | (n as u8) << 3);
+      },
+      _ => return Err(format!("unknown instruction {}", mnemonic)),
+    }
+    Ok(())
+  }
+
+  fn operand<'a>(&self, line: &'a Line, i: usize) -> Result<&'a str, String> {
+    line.operands.get(i).map(|operand| operand.as_str()).ok_or(String::from("missing operand"))
+  }
+
+  fn emit(&mut self, db: u8) {
+    self.output.insert(self.pc, db);
+    self.pc = self.pc.wrapping_add(1);
+  }
+
+  fn emit_word(&mut self, hblb: u16) {
+    self.emit(hblb as u8);
+    self.emit((hblb >> 8) as u8);
+  }
+
+  fn reg(&self, operand: &str) -> Result<u8, String> {
+    let name = operand.trim().to_uppercase();
+    match REGS.iter().position(|reg| *reg == name) {
+      Some(i) => Ok(i as u8),
+      None => match self.eval(operand)? {
+        value @ 0..=7 => Ok(value as u8),
+        _ => Err(format!("expected a register, found {}", operand)),
+      },
+    }
+  }
+
+  fn pair(&self, operand: &str, names: &[&str]) -> Result<u8, String> {
+    let name = operand.trim().to_uppercase();
+    match names.iter().position(|pair| *pair == name) {
+      Some(i) => Ok(i as u8),
+      None => Err(format!("expected one of {}, found {}", names.join(" "), operand)),
+    }
+  }
+
+  fn byte(&self, operand: &str) -> Result<u8, String> {
+    match self.eval(operand)? {
+      value @ -0x80..=0xFF => Ok(value as u8),
+      value => Err(format!("{} does not fit in a byte", value)),
+    }
+  }
+
+  fn word(&self, operand: &str) -> Result<u16, String> {
+    match self.eval(operand)? {
+      value @ -0x8000..=0xFFFF => Ok(value as u16),
+      value => Err(format!("{} does not fit in a word", value)),
+    }
+  }
+
+  /// Evaluate an expression. Before the final pass undefined symbols read
+  /// as zero, as only the size of each line matters then.
+  fn eval(&self, text: &str) -> Result<i64, String> {
+    let tokens = tokenize(text)?;
+    let mut parser = Parser { tokens: &tokens, i: 0, asm: self, strict: self.final_pass };
+    let value = parser.or_expr()?;
+    if parser.i != tokens.len() {
+      return Err(format!("unexpected {:?} in '{}'", tokens[parser.i], text));
+    }
+    Ok(value)
+  }
+
+  /// Evaluate an expression that must be known in the first pass
+  fn eval_defined(&self, text: &str) -> Result<i64, String> {
+    let tokens = tokenize(text)?;
+    let mut parser = Parser { tokens: &tokens, i: 0, asm: self, strict: true };
+    let value = parser.or_expr()?;
+    if parser.i != tokens.len() {
+      return Err(format!("unexpected {:?} in '{}'", tokens[parser.i], text));
+    }
+    Ok(value)
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+  Number(i64),
+  Ident(String),
+  Op(char),
+}
+
+struct Parser<'a> {
+  tokens: &'a [Token],
+  i: usize,
+  asm: &'a Assembler,
+  strict: bool,
+}
+
+impl Parser<'_> {
+  fn peek_word(&self, word: &str) -> bool {
+    matches!(self.tokens.get(self.i), Some(Token::Ident(name)) if name == word)
+  }
+
+  fn peek_op(&self, op: char) -> bool {
+    self.tokens.get(self.i) == Some(&Token::Op(op))
+  }
+
+  fn or_expr(&mut self) -> Result<i64, String> {
+    let mut value = self.and_expr()?;
+    loop {
+      if self.peek_word("OR") {
+        self.i += 1;
+        value |= self.and_expr()?;
+      } else if self.peek_word("XOR") {
+        self.i += 1;
+        value ^= self.and_expr()?;
+      } else {
+        return Ok(value);
+      }
+    }
+  }
+
+  fn and_expr(&mut self) -> Result<i64, String> {
+    let mut value = self.not_expr()?;
+    while self.peek_word("AND") {
+      self.i += 1;
+      value &= self.not_expr()?;
+    }
+    Ok(value)
+  }
+
+  fn not_expr(&mut self) -> Result<i64, String> {
+    if self.peek_word("NOT") {
+      self.i += 1;
+      return Ok(!self.not_expr()? & 0xFFFF);
+    }
+    self.add_expr()
+  }
+
+  fn add_expr(&mut self) -> Result<i64, String> {
+    let mut value = self.mul_expr()?;
+    loop {
+      if self.peek_op('+') {
+        self.i += 1;
+        value += self.mul_expr()?;
+      } else if self.peek_op('-') {
+        self.i += 1;
+        value -= self.mul_expr()?;
+      } else {
+        return Ok(value);
+      }
+    }
+  }
+
+  fn mul_expr(&mut self) -> Result<i64, String> {
+    let mut value = self.unary()?;
+    loop {
+      if self.peek_op('*') {
+        self.i += 1;
+        value *= self.unary()?;
+      } else if self.peek_op('/') || self.peek_word("MOD") {
+        let modulo = !self.peek_op('/');
+        self.i += 1;
+        let divisor = self.unary()?;
+        if divisor == 0 {
+          return Err(String::from("division by zero"));
+        }
+        value = if modulo { value % divisor } else { value / divisor };
+      } else if self.peek_word("SHL") {
+        self.i += 1;
+        value = (value << (self.unary()? & 0x1F)) & 0xFFFF;
+      } else if self.peek_word("SHR") {
+        self.i += 1;
+        value = (value & 0xFFFF) >> (self.unary()? & 0x1F);
+      } else {
+        return Ok(value);
+      }
+    }
+  }
+
+  fn unary(&mut self) -> Result<i64, String> {
+    if self.peek_op('-') {
+      self.i += 1;
+      return Ok(-self.unary()?);
+    }
+    if self.peek_op('+') {
+      self.i += 1;
+      return self.unary();
+    }
+    if self.peek_word("HIGH") {
+      self.i += 1;
+      return Ok((self.unary()? >> 8) & 0xFF);
+    }
+    if self.peek_word("LOW") {
+      self.i += 1;
+      return Ok(self.unary()? & 0xFF);
+    }
+    self.primary()
+  }
+
+  fn primary(&mut self) -> Result<i64, String> {
+    let token = self.tokens.get(self.i).cloned().ok_or(String::from("missing operand"))?;
+    self.i += 1;
+    match token {
+      Token::Number(value) => Ok(value),
+      Token::Op('$') => Ok(self.asm.here as i64),
+      Token::Op('(') => {
+        let value = self.or_expr()?;
+        if !self.peek_op(')') {
+          return Err(String::from("missing ')'"));
+        }
+        self.i += 1;
+        Ok(value)
+      },
+      Token::Ident(name) => match self.asm.symbols.get(&name) {
+        Some(&value) => Ok(value as i64),
+        None if !self.strict => Ok(0),
+        None => Err(format!("undefined symbol {}", name)),
+      },
+      Token::Op(op) => Err(format!("unexpected '{}'", op)),
+    }
+  }
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+  let chars: Vec<char> = text.chars().collect();
+  let mut tokens = Vec::new();
+  let mut i = 0;
+  while i < chars.len() {
+    let c = chars[i];
+    if c.is_whitespace() {
+      i += 1;
+    } else if c == '\'' {
+      let mut value: i64 = 0;
+      let mut len = 0;
+      i += 1;
+      loop {
+        match (chars.get(i), chars.get(i + 1)) {
+          (Some('\''), Some('\'')) => i += 1,
+          (Some('\''), _) => break,
+          (None, _) => return Err(String::from("unterminated character constant")),
+          _ => (),
+        }
+        value = (value << 8) | (chars[i] as i64 & 0xFF);
+        len += 1;
+        i += 1;
+      }
+      if len == 0 || len > 2 {
+        return Err(String::from("character constant must be one or two characters"));
+      }
+      tokens.push(Token::Number(value));
+      i += 1;
+    } else if c.is_ascii_digit() {
+      let start = i;
+      while i < chars.len() && chars[i].is_ascii_alphanumeric() {
+        i += 1;
+      }
+      let text: String = chars[start..i].iter().collect();
+      tokens.push(Token::Number(number(&text)?));
+    } else if is_ident_char(c) {
+      let start = i;
+      while i < chars.len() && (is_ident_char(chars[i]) || chars[i].is_ascii_digit()) {
+        i += 1;
+      }
+      tokens.push(Token::Ident(chars[start..i].iter().collect::<String>().to_uppercase()));
+    } else if "+-*/()$".contains(c) {
+      tokens.push(Token::Op(c));
+      i += 1;
+    } else {
+      return Err(format!("unexpected '{}'", c));
+    }
+  }
+  Ok(tokens)
+}
+
+fn is_ident_char(c: char) -> bool {
+  c.is_ascii_alphabetic() || c == '_' || c == '?' || c == '@' || c == '.'
+}
+
+/// Numeric literal with an optional radix suffix
+fn number(text: &str) -> Result<i64, String> {
+  let upper = text.to_uppercase();
+  let parsed = if let Some(hex) = upper.strip_prefix("0X") {
+    i64::from_str_radix(hex, 16)
+  } else if let Some(hex) = upper.strip_suffix('H') {
+    i64::from_str_radix(hex, 16)
+  } else if let Some(bin) = upper.strip_suffix('B') {
+    i64::from_str_radix(bin, 2)
+  } else if let Some(oct) = upper.strip_suffix('O').or_else(|| upper.strip_suffix('Q')) {
+    i64::from_str_radix(oct, 8)
+  } else {
+    upper.strip_suffix('D').unwrap_or(&upper).parse()
+  };
+  parsed.map_err(|_| format!("invalid number {}", text))
+}
+
+/// Prefix and condition code of a conditional jump, call or return
+fn conditional(mnemonic: &str) -> Option<(char, u8)> {
+  let mut chars = mnemonic.chars();
+  let prefix = chars.next().filter(|c| "JCR".contains(*c))?;
+  let condition = chars.as_str();
+  CONDITIONS.iter().position(|cc| *cc == condition).map(|ccc| (prefix, ccc as u8))
+}
+
+fn is_mnemonic(word: &str) -> bool {
+  const OTHERS: [&str; 38] = [
+    "NOP", "RLC", "RRC", "RAL", "RAR", "DAA", "CMA", "STC", "CMC", "HLT", "RET", "XTHL", "PCHL",
+    "XCHG", "DI", "SPHL", "EI", "MOV", "MVI", "INR", "DCR", "LXI", "INX", "DCX", "DAD", "STAX",
+    "LDAX", "PUSH", "POP", "JMP", "CALL", "LDA", "STA", "LHLD", "SHLD", "IN", "OUT", "RST",
+  ];
+  DIRECTIVES.contains(&word) || OTHERS.contains(&word) || ALU.contains(&word) ||
+    ALU_IMM.contains(&word) || conditional(word).is_some()
+}
+
+/// Word at the start of a line: an identifier, empty if there is none
+fn leading_word(text: &str) -> &str {
+  let end = text
+    .char_indices()
+    .find(|&(_, c)| !(is_ident_char(c) || c.is_ascii_digit()))
+    .map(|(i, _)| i)
+    .unwrap_or(text.len());
+  if text.starts_with(|c: char| c.is_ascii_digit()) {
+    ""
+  } else {
+    &text[..end]
+  }
+}
+
+/// Remove a `;` comment, ignoring semicolons inside quotes
+fn strip_comment(text: &str) -> &str {
+  let mut quoted = false;
+  for (i, c) in text.char_indices() {
+    match c {
+      '\'' => quoted = !quoted,
+      ';' if !quoted => return &text[..i],
+      _ => (),
+    }
+  }
+  text
+}
+
+/// Split on commas outside quotes and parentheses
+fn split_operands(text: &str) -> Vec<String> {
+  if text.is_empty() {
+    return Vec::new();
+  }
+  let mut operands = Vec::new();
+  let mut quoted = false;
+  let mut depth = 0;
+  let mut start = 0;
+  for (i, c) in text.char_indices() {
+    match c {
+      '\'' => quoted = !quoted,
+      '(' if !quoted => depth += 1,
+      ')' if !quoted => depth -= 1,
+      ',' if !quoted && depth == 0 => {
+        operands.push(text[start..i].trim().to_string());
+        start = i + 1;
+      },
+      _ => (),
+    }
+  }
+  operands.push(text[start..].trim().to_string());
+  operands
+}
+
+/// Contents of a quoted string operand
+fn string_literal(operand: &str) -> Option<String> {
+  let inner = operand.strip_prefix('\'')?.strip_suffix('\'')?;
+  if inner.is_empty() {
+    return None;
+  }
+  Some(inner.replace("''", "'"))
+}
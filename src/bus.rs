@@ -0,0 +1,49 @@
+use std::fmt;
+
+/// Address space seen by the processor
+pub trait Bus {
+  /// Read the byte at a 16-bit address
+  fn read(&self, hblb: u16) -> u8;
+
+  /// Write a byte to a 16-bit address
+  fn write(&mut self, hblb: u16, db: u8);
+
+  /// Called after every instruction with the number of cycles it took
+  fn tick(&mut self, _cycles: usize) {}
+}
+
+/// Port space accessed by the IN and OUT instructions
+pub trait IoBus {
+  /// Value driven onto the data bus for IN from `port`
+  fn input(&mut self, port: u8) -> u8;
+
+  /// Value written by OUT to `port`
+  fn output(&mut self, port: u8, db: u8);
+}
+
+/// Nothing attached: inputs read as a floating bus and outputs are dropped
+pub struct NullIo;
+
+impl IoBus for NullIo {
+  fn input(&mut self, _port: u8) -> u8 {
+    0xFF
+  }
+
+  fn output(&mut self, _port: u8, _db: u8) {}
+}
+
+/// Direction and number of an IN or OUT
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PortAccess {
+  In(u8),
+  Out(u8),
+}
+
+impl fmt::Display for PortAccess {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      PortAccess::In(port) => write!(f, "IN {:02X}", port),
+      PortAccess::Out(port) => write!(f, "OUT {:02X}", port),
+    }
+  }
+}
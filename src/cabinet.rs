@@ -0,0 +1,103 @@
+/// Player buttons, shared by both players
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Controls {
+  pub fire: bool,
+  pub left: bool,
+  pub right: bool,
+}
+
+impl Controls {
+  /// Fire, left and right as bits 4, 5 and 6
+  fn bits(self) -> u8 {
+    (self.fire as u8) << 4 | (self.left as u8) << 5 | (self.right as u8) << 6
+  }
+}
+
+/// DIP switch bank, read through port 2
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DipSwitches {
+  /// Ships per game, 3 to 6
+  pub ships: u8,
+  /// Extra ship at 1000 points instead of 1500
+  pub extra_ship_at_1000: bool,
+  /// Show the coin information on the attract screen
+  pub coin_info: bool,
+}
+
+impl Default for DipSwitches {
+  /// Factory settings: 3 ships, extra ship at 1500, coin info shown
+  fn default() -> DipSwitches {
+    DipSwitches { ships: 3, extra_ship_at_1000: false, coin_info: true }
+  }
+}
+
+impl DipSwitches {
+  pub fn set_ships(&mut self, ships: u8) -> Result<(), String> {
+    if !(3..=6).contains(&ships) {
+      return Err(format!("{} ships, expected 3 to 6", ships));
+    }
+    self.ships = ships;
+    Ok(())
+  }
+
+  /// Score for the extra ship, 1000 or 1500
+  pub fn set_extra_ship_at(&mut self, score: u16) -> Result<(), String> {
+    match score {
+      1000 | 1500 => self.extra_ship_at_1000 = score == 1000,
+      _ => return Err(format!("extra ship at {}, expected 1000 or 1500", score)),
+    }
+    Ok(())
+  }
+
+  /// Ships in bits 0 and 1, bonus in bit 3 and coin info, active low, in
+  /// bit 7
+  fn bits(self) -> u8 {
+    (self.ships.clamp(3, 6) - 3) | (self.extra_ship_at_1000 as u8) << 3 | (!self.coin_info as u8) << 7
+  }
+}
+
+/// Everything the cabinet wires to input ports 0, 1 and 2
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CabinetInputs {
+  pub coin: bool,
+  pub p1_start: bool,
+  pub p2_start: bool,
+  pub p1: Controls,
+  pub p2: Controls,
+  pub tilt: bool,
+  pub dips: DipSwitches,
+}
+
+impl CabinetInputs {
+  pub fn new() -> CabinetInputs {
+    CabinetInputs::default()
+  }
+
+  /// Port 0: bits 1 to 3 are tied high and bits 4 to 6 repeat the player 1
+  /// controls. The Midway ROM never reads it.
+  pub fn port0(&self) -> u8 {
+    0x0E | self.p1.bits()
+  }
+
+  /// Port 1: coin in bit 0, player 2 and player 1 start in bits 1 and 2,
+  /// bit 3 tied high and the player 1 controls in bits 4 to 6
+  pub fn port1(&self) -> u8 {
+    self.coin as u8 | (self.p2_start as u8) << 1 | (self.p1_start as u8) << 2 | 0x08 | self.p1.bits()
+  }
+
+  /// Port 2: the DIP switches, tilt in bit 2 and the player 2 controls in
+  /// bits 4 to 6
+  pub fn port2(&self) -> u8 {
+    self.dips.bits() | (self.tilt as u8) << 2 | self.p2.bits()
+  }
+
+  /// Value of an input port, `None` for ports other than 0, 1 and 2
+  pub fn read(&self, port: u8) -> Option<u8> {
+    match port {
+      0 => Some(self.port0()),
+      1 => Some(self.port1()),
+      2 => Some(self.port2()),
+      _ => None,
+    }
+  }
+}
@@ -0,0 +1,293 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use crate::bus::Bus;
+use crate::disasm::{Flow, Instruction, Operand};
+use crate::symbols::Symbols;
+use crate::trace::{Image, Trace};
+
+/// Most entries read from a PCHL jump table
+const MAX_TABLE: usize = 64;
+
+/// Edge leaving a basic block within its subroutine
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Edge {
+  /// Falls through into the next block, or a conditional branch not taken
+  Fall(u16),
+  Jump(u16),
+  /// Conditional branch taken
+  Taken(u16),
+  /// PCHL through a jump table
+  Table(u16),
+}
+
+impl Edge {
+  pub fn target(self) -> u16 {
+    match self {
+      Edge::Fall(hblb) | Edge::Jump(hblb) | Edge::Taken(hblb) | Edge::Table(hblb) => hblb,
+    }
+  }
+}
+
+/// Straight-line run of instructions with a single entry
+pub struct Block {
+  pub start: u16,
+  /// Addresses of the instructions in the block
+  pub instructions: Vec<u16>,
+  pub edges: Vec<Edge>,
+  /// Targets of CALL, conditional calls and RST inside the block
+  pub calls: Vec<u16>,
+  /// Ends in RET or a conditional return
+  pub returns: bool,
+}
+
+/// Control-flow graph of an image, built from a `Trace` so it follows the
+/// same decoding as the disassembler and `Processor`
+pub struct Cfg {
+  pub trace: Trace,
+  pub blocks: BTreeMap<u16, Block>,
+  /// Entry points and every call target
+  pub subroutines: BTreeSet<u16>,
+  /// Targets of each PCHL whose jump table could be found
+  pub tables: BTreeMap<u16, Vec<u16>>,
+}
+
+impl Cfg {
+  /// Trace from `entries`, tracing again from any jump table targets found
+  /// until there are no new ones, then split the code into blocks
+  pub fn new(image: &Image, entries: &[u16]) -> Cfg {
+    let mut roots = entries.to_vec();
+    let (trace, tables) = loop {
+      let trace = Trace::new(image, &roots);
+      let tables = find_tables(image, &trace);
+      let new: BTreeSet<u16> = tables.values().flatten().copied().filter(|target| !roots.contains(target)).collect();
+      if new.is_empty() {
+        break (trace, tables);
+      }
+      roots.extend(new);
+    };
+    let tables: BTreeMap<u16, Vec<u16>> = tables
+      .into_iter()
+      .map(|(pchl, targets)| (pchl, targets.into_iter().filter(|target| trace.instructions.contains_key(target)).collect()))
+      .collect();
+
+    let mut leaders: BTreeSet<u16> = entries.iter().copied().filter(|hblb| trace.instructions.contains_key(hblb)).collect();
+    leaders.extend(&trace.labels);
+    leaders.extend(tables.values().flatten());
+    for instruction in trace.instructions.values() {
+      if ends_block(instruction) {
+        leaders.insert(instruction.next());
+      }
+    }
+
+    let mut blocks = BTreeMap::new();
+    for &start in leaders.iter().filter(|hblb| trace.instructions.contains_key(hblb)) {
+      let mut block = Block { start, instructions: Vec::new(), edges: Vec::new(), calls: Vec::new(), returns: false };
+      let mut hblb = start;
+      while let Some(instruction) = trace.instructions.get(&hblb) {
+        block.instructions.push(hblb);
+        match instruction.flow {
+          Flow::Call(target) | Flow::CondCall(target) => block.calls.push(target),
+          Flow::Jump(target) => block.edges.push(Edge::Jump(target)),
+          Flow::Branch(target) => block.edges.extend([Edge::Taken(target), Edge::Fall(instruction.next())]),
+          Flow::Return => block.returns = true,
+          Flow::CondReturn => {
+            block.returns = true;
+            block.edges.push(Edge::Fall(instruction.next()));
+          },
+          Flow::Indirect => block.edges.extend(tables.get(&hblb).into_iter().flatten().map(|&target| Edge::Table(target))),
+          Flow::Next | Flow::Halt => (),
+        }
+        if ends_block(instruction) {
+          break;
+        }
+        hblb = instruction.next();
+        if leaders.contains(&hblb) {
+          if trace.instructions.contains_key(&hblb) {
+            block.edges.push(Edge::Fall(hblb));
+          }
+          break;
+        }
+      }
+      blocks.insert(start, block);
+    }
+
+    let mut subroutines: BTreeSet<u16> = entries.iter().copied().filter(|hblb| blocks.contains_key(hblb)).collect();
+    subroutines.extend(blocks.values().flat_map(|block| block.calls.iter()).filter(|hblb| blocks.contains_key(hblb)));
+    Cfg { trace, blocks, subroutines, tables }
+  }
+
+  /// Blocks reachable from `entry` without following calls
+  pub fn subroutine(&self, entry: u16) -> BTreeSet<u16> {
+    let mut reached = BTreeSet::new();
+    let mut pending = vec![entry];
+    while let Some(start) = pending.pop() {
+      if let Some(block) = self.blocks.get(&start) {
+        if reached.insert(start) {
+          pending.extend(block.edges.iter().map(|edge| edge.target()));
+        }
+      }
+    }
+    reached
+  }
+
+  /// Whole program as DOT, one cluster per subroutine and calls as dashed
+  /// edges. A block shared by several subroutines is drawn in the first.
+  pub fn dot(&self, symbols: &Symbols) -> String {
+    let mut out = String::new();
+    writeln!(out, "digraph program {{").unwrap();
+    writeln!(out, "  node [shape=box fontname=\"monospace\"];").unwrap();
+    let mut drawn = BTreeSet::new();
+    for &entry in &self.subroutines {
+      let blocks: Vec<u16> = self.subroutine(entry).into_iter().filter(|start| !drawn.contains(start)).collect();
+      writeln!(out, "  subgraph cluster_{:04X} {{", entry).unwrap();
+      writeln!(out, "    label=\"{}\";", escape(&symbols.label(entry))).unwrap();
+      for start in blocks {
+        write!(out, "  ").unwrap();
+        self.node(&mut out, start, symbols);
+        drawn.insert(start);
+      }
+      writeln!(out, "  }}").unwrap();
+    }
+    for &start in self.blocks.keys().filter(|start| !drawn.contains(start)) {
+      self.node(&mut out, start, symbols);
+    }
+    for block in self.blocks.values() {
+      self.edges(&mut out, block);
+      for &target in block.calls.iter().filter(|target| self.blocks.contains_key(target)) {
+        writeln!(out, "  b{:04X} -> b{:04X} [style=dashed];", block.start, target).unwrap();
+      }
+    }
+    writeln!(out, "}}").unwrap();
+    out
+  }
+
+  /// One subroutine as DOT, with the names of called subroutines in the
+  /// block text
+  pub fn subroutine_dot(&self, entry: u16, symbols: &Symbols) -> String {
+    let mut out = String::new();
+    writeln!(out, "digraph \"{}\" {{", escape(&symbols.label(entry))).unwrap();
+    writeln!(out, "  node [shape=box fontname=\"monospace\"];").unwrap();
+    let blocks = self.subroutine(entry);
+    for &start in &blocks {
+      self.node(&mut out, start, symbols);
+    }
+    for start in blocks {
+      self.edges(&mut out, &self.blocks[&start]);
+    }
+    writeln!(out, "}}").unwrap();
+    out
+  }
+
+  fn node(&self, out: &mut String, start: u16, symbols: &Symbols) {
+    let block = &self.blocks[&start];
+    let mut text = format!("{}:\\l", escape(&symbols.label(start)));
+    for hblb in &block.instructions {
+      let instruction = &self.trace.instructions[hblb];
+      write!(text, "{:04X}  {}\\l", hblb, escape(&self.instruction_text(instruction, symbols))).unwrap();
+    }
+    writeln!(out, "  b{:04X} [label=\"{}\"];", start, text).unwrap();
+  }
+
+  fn edges(&self, out: &mut String, block: &Block) {
+    for edge in &block.edges {
+      let style = match edge {
+        Edge::Fall(_) => "",
+        Edge::Jump(_) => " [color=blue]",
+        Edge::Taken(_) => " [color=darkgreen label=\"T\"]",
+        Edge::Table(_) => " [style=dotted]",
+      };
+      writeln!(out, "  b{:04X} -> b{:04X}{};", block.start, edge.target(), style).unwrap();
+    }
+  }
+
+  /// Instruction with jump and call targets named, using block labels for
+  /// code that has no symbol
+  fn instruction_text(&self, instruction: &Instruction, symbols: &Symbols) -> String {
+    let operands: Vec<String> = instruction.operands.iter().map(|operand| match *operand {
+      Operand::Addr(hblb) if instruction.target() == Some(hblb) && self.blocks.contains_key(&hblb) => symbols.label(hblb),
+      Operand::Addr(hblb) => symbols.name(hblb).map(String::from).unwrap_or(operand.to_string()),
+      _ => operand.to_string(),
+    }).collect();
+    if operands.is_empty() {
+      instruction.mnemonic.to_string()
+    } else {
+      format!("{} {}", instruction.mnemonic, operands.join(","))
+    }
+  }
+}
+
+/// Control can not fall through into the next instruction on every path
+fn ends_block(instruction: &Instruction) -> bool {
+  matches!(instruction.flow, Flow::Jump(_) | Flow::Branch(_) | Flow::Return | Flow::CondReturn | Flow::Indirect | Flow::Halt)
+}
+
+/// Jump tables behind PCHL. A table is recognised when the block loading
+/// the jump address sets HL or DE to a table in the image with LXI and
+/// reads a word from it, `MOV E,M / INX H / MOV D,M` or
+/// `MOV A,M / INX H / MOV H,M`, before the PCHL. Entries are read until one
+/// points outside the image or into the middle of an instruction.
+fn find_tables(image: &Image, trace: &Trace) -> BTreeMap<u16, Vec<u16>> {
+  let mut tables = BTreeMap::new();
+  for (&pchl, instruction) in &trace.instructions {
+    if instruction.flow != Flow::Indirect {
+      continue;
+    }
+    // Instructions leading up to the PCHL, stopping at a jump target
+    let mut before = Vec::new();
+    let mut hblb = pchl;
+    while let Some((&previous, instruction)) = trace.instructions.range(..hblb).next_back() {
+      if instruction.next() != hblb || ends_block(instruction) {
+        break;
+      }
+      before.push(instruction);
+      hblb = previous;
+      if trace.labels.contains(&hblb) {
+        break;
+      }
+    }
+    before.reverse();
+    let text: Vec<String> = before.iter().map(|instruction| instruction.to_string()).collect();
+    let reads = text.windows(3).any(|w| {
+      (w[0] == "MOV E,M" && w[1] == "INX H" && w[2] == "MOV D,M") ||
+        (w[0] == "MOV A,M" && w[1] == "INX H" && w[2] == "MOV H,M")
+    });
+    let base = before.iter().rev().find_map(|instruction| match instruction.operands[..] {
+      [Operand::Pair("H" | "D"), Operand::Imm16(base)] if instruction.mnemonic == "LXI" => Some(base),
+      _ => None,
+    });
+    let Some(base) = base.filter(|_| reads) else {
+      continue;
+    };
+
+    let mut targets = Vec::new();
+    let mut entry = base as usize;
+    while targets.len() < MAX_TABLE && image.contains(entry + 1) && !inside_code(trace, entry) && !inside_code(trace, entry + 1) {
+      let target = u16::from_le_bytes([image.read(entry as u16), image.read(entry as u16 + 1)]);
+      if !image.contains(target as usize) || mid_instruction(trace, target) {
+        break;
+      }
+      targets.push(target);
+      entry += 2;
+    }
+    if !targets.is_empty() {
+      tables.insert(pchl, targets);
+    }
+  }
+  tables
+}
+
+/// The byte at `hblb` belongs to a traced instruction
+fn inside_code(trace: &Trace, hblb: usize) -> bool {
+  trace.instructions.range(..=hblb as u16).next_back().is_some_and(|(&start, instruction)| hblb < start as usize + instruction.len())
+}
+
+/// `hblb` falls after the first byte of a traced instruction
+fn mid_instruction(trace: &Trace, hblb: u16) -> bool {
+  inside_code(trace, hblb as usize) && !trace.instructions.contains_key(&hblb)
+}
+
+/// Quote characters that are special in a DOT string
+fn escape(text: &str) -> String {
+  text.replace('\\', "\\\\").replace('"', "\\\"")
+}
@@ -0,0 +1,75 @@
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+use serde::Deserialize;
+
+use crate::cabinet::DipSwitches;
+
+/// Cabinet settings read from a TOML file:
+///
+/// ```toml
+/// [dip_switches]
+/// ships = 4
+/// extra_ship_at = 1000
+/// coin_info = false
+///
+/// [sound]
+/// samples = "samples"
+/// ```
+///
+/// Anything left out keeps its factory setting. A relative sample
+/// directory is taken from the directory of the file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Config {
+  pub dips: DipSwitches,
+  /// Directory holding `0.wav` to `8.wav`
+  pub samples: Option<PathBuf>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct File {
+  dip_switches: Option<Dips>,
+  sound: Option<Sound>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Sound {
+  samples: Option<PathBuf>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Dips {
+  ships: Option<u8>,
+  extra_ship_at: Option<u16>,
+  coin_info: Option<bool>,
+}
+
+impl Config {
+  pub fn load(path: &Path) -> io::Result<Config> {
+    let text = fs::read_to_string(path)?;
+    let mut config = Config::parse(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    if let (Some(samples), Some(dir)) = (&config.samples, path.parent()) {
+      config.samples = Some(dir.join(samples));
+    }
+    Ok(config)
+  }
+
+  pub fn parse(text: &str) -> Result<Config, String> {
+    let file: File = toml::from_str(text).map_err(|e| e.message().to_string())?;
+    let mut config = Config::default();
+    if let Some(dips) = file.dip_switches {
+      if let Some(ships) = dips.ships {
+        config.dips.set_ships(ships)?;
+      }
+      if let Some(score) = dips.extra_ship_at {
+        config.dips.set_extra_ship_at(score)?;
+      }
+      config.dips.coin_info = dips.coin_info.unwrap_or(config.dips.coin_info);
+    }
+    config.samples = file.sound.and_then(|sound| sound.samples);
+    Ok(config)
+  }
+}
@@ -0,0 +1,488 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+
+use crate::bus::{Bus, NullIo};
+use crate::processor::{HaltPolicy, Processor};
+
+const WBOOT: usize = 0x0000;
+const BDOS_JMP: usize = 0x0005;
+const FCB1: usize = 0x005C;
+const FCB2: usize = 0x006C;
+const DEFAULT_DMA: usize = 0x0080;
+pub const TPA: usize = 0x0100;
+const BDOS: usize = 0xFE00;
+const BIOS: usize = 0xFF00;
+const BIOS_ENTRIES: usize = 17;
+
+const RECORD_SIZE: usize = 128;
+const EOF: u8 = 0x1A;
+
+/// CP/M 2.2 environment: a flat 64 KiB machine with the BDOS and BIOS
+/// implemented on the host, files served from a host directory
+pub struct Cpm {
+  pub cpu: Processor,
+  dir: PathBuf,
+  dma: usize,
+  search: Vec<[u8; 11]>,
+  /// Console input, stdin unless replaced. Stdin is only locked while a
+  /// byte is read.
+  pub input: Box<dyn Read>,
+  /// Console output is collected here instead of written to stdout when set
+  pub console: Option<Vec<u8>>,
+}
+
+impl Cpm {
+  pub fn new(program: &[u8], dir: PathBuf, args: &[String]) -> io::Result<Cpm> {
+    if program.len() > BDOS - TPA {
+      return Err(io::Error::new(io::ErrorKind::InvalidData, "program does not fit in the TPA"));
+    }
+    let mut cpu = Processor::flat();
+    let mut poke = |hblb: usize, db: u8| cpu.mem.write(hblb as u16, db);
+    for (i, &db) in program.iter().enumerate() {
+      poke(TPA + i, db);
+    }
+
+    // Page zero vectors into the BIOS warm boot entry and the BDOS
+    poke(WBOOT, 0xC3);
+    poke(WBOOT + 1, (BIOS + 3) as u8);
+    poke(WBOOT + 2, ((BIOS + 3) >> 8) as u8);
+    poke(BDOS_JMP, 0xC3);
+    poke(BDOS_JMP + 1, BDOS as u8);
+    poke(BDOS_JMP + 2, (BDOS >> 8) as u8);
+    // Trapped entry points return to the caller with a RET
+    poke(BDOS, 0xC9);
+    for i in 0..BIOS_ENTRIES {
+      poke(BIOS + 3 * i, 0xC9);
+    }
+
+    // Command tail and default FCBs, as the CCP would leave them
+    let tail: String = args.iter().map(|arg| format!(" {}", arg.to_uppercase())).collect();
+    let tail = &tail.as_bytes()[..tail.len().min(RECORD_SIZE - 1)];
+    poke(DEFAULT_DMA, tail.len() as u8);
+    for (i, &db) in tail.iter().enumerate() {
+      poke(DEFAULT_DMA + 1 + i, db);
+    }
+    for (fcb, arg) in [FCB1, FCB2].into_iter().zip(args.iter().map(Some).chain([None, None])) {
+      let name = arg.map(|arg| fcb_name(arg)).unwrap_or([b' '; 11]);
+      poke(fcb, 0);
+      for (i, &db) in name.iter().enumerate() {
+        poke(fcb + 1 + i, db);
+      }
+    }
+
+    let mut state = cpu.state();
+    state.pc = TPA as u16;
+    state.sp = (BDOS - 2) as u16;
+    cpu.set_state(&state);
+    cpu.halt_policy = HaltPolicy::Stop;
+
+    Ok(Cpm { cpu, dir, dma: DEFAULT_DMA, search: Vec::new(), input: Box::new(io::stdin()), console: None })
+  }
+
+  /// Run until the program warm boots or halts for good
+  pub fn run(&mut self) {
+    loop {
+      let running = match self.cpu.state().pc as usize {
+        _ if self.cpu.is_stopped() => false,
+        WBOOT => false,
+        BDOS => self.bdos(),
+        pc if (BIOS..BIOS + 3 * BIOS_ENTRIES).contains(&pc) && (pc - BIOS).is_multiple_of(3) => self.bios((pc - BIOS) / 3),
+        _ => true,
+      };
+      if !running {
+        io::stdout().flush().ok();
+        return;
+      }
+      self.cpu.exec(&mut NullIo);
+    }
+  }
+
+  /// Handle a BDOS call, returns false if the program ended
+  fn bdos(&mut self) -> bool {
+    let state = self.cpu.state();
+    let de = state.de() as usize;
+    let ret: u16 = match state.c {
+      0x00 => return false,
+      0x01 => {
+        let db = self.conin();
+        self.conout(db);
+        db as u16
+      },
+      0x02 => {
+        self.conout(state.e);
+        0
+      },
+      0x06 => match state.e {
+        0xFF => self.conin() as u16,
+        0xFE => 0,
+        db => {
+          self.conout(db);
+          0
+        },
+      },
+      0x09 => {
+        // Give up after all of memory without finding the '$'
+        for hblb in de..de + 0x10000 {
+          match self.peek(hblb) {
+            b'$' => break,
+            db => self.conout(db),
+          }
+        }
+        0
+      },
+      0x0A => {
+        self.read_line(de);
+        0
+      },
+      0x0B | 0x0E | 0x19 | 0x20 => 0,
+      0x0C => 0x0022,
+      0x0D => {
+        self.dma = DEFAULT_DMA;
+        0
+      },
+      0x0F => self.open(de),
+      0x10 => 0,
+      0x11 => self.search_first(de),
+      0x12 => self.search_next(),
+      0x13 => self.delete(de),
+      0x14 => self.read_sequential(de),
+      0x15 => self.write_sequential(de),
+      0x16 => self.make(de),
+      0x17 => self.rename(de),
+      0x18 => 0x0001,
+      0x1A => {
+        self.dma = de;
+        0
+      },
+      0x21 => self.read_random(de),
+      0x22 | 0x28 => self.write_random(de),
+      0x23 => self.file_size(de),
+      0x24 => {
+        let record = self.sequential_record(de);
+        self.set_random_record(de, record);
+        0
+      },
+      _ => 0,
+    };
+    // Returned in HL, and A and B for older programs
+    let mut state = self.cpu.state();
+    state.set_hl(ret);
+    state.a = state.l;
+    state.b = state.h;
+    self.cpu.set_state(&state);
+    true
+  }
+
+  /// Handle a BIOS call, returns false if the program ended
+  fn bios(&mut self, entry: usize) -> bool {
+    let mut state = self.cpu.state();
+    match entry {
+      0 | 1 => return false,
+      2 => state.a = 0x00,
+      3 => state.a = self.conin(),
+      4..=6 => self.conout(state.c),
+      7 => state.a = EOF,
+      9 => state.set_hl(0),
+      _ => state.a = 0x00,
+    }
+    self.cpu.set_state(&state);
+    true
+  }
+
+  fn peek(&self, hblb: usize) -> u8 {
+    self.cpu.mem.read(hblb as u16)
+  }
+
+  fn poke(&mut self, hblb: usize, db: u8) {
+    self.cpu.mem.write(hblb as u16, db);
+  }
+
+  fn conin(&mut self) -> u8 {
+    io::stdout().flush().ok();
+    let mut db = [0x00];
+    match self.input.read(&mut db) {
+      Ok(1) if db[0] == b'\n' => b'\r',
+      Ok(1) => db[0],
+      _ => EOF,
+    }
+  }
+
+  fn conout(&mut self, db: u8) {
+    match &mut self.console {
+      Some(console) => console.push(db),
+      None => {
+        io::stdout().write_all(&[db]).ok();
+      },
+    }
+  }
+
+  /// Buffered console input into the buffer at `hblb`
+  fn read_line(&mut self, hblb: usize) {
+    let max = self.peek(hblb) as usize;
+    let mut count = 0;
+    while count < max {
+      match self.conin() {
+        b'\r' | EOF => break,
+        db => {
+          self.poke(hblb + 2 + count, db);
+          count += 1;
+        },
+      }
+    }
+    self.poke(hblb + 1, count as u8);
+  }
+
+  fn fcb_filename(&self, fcb: usize) -> [u8; 11] {
+    let mut name = [b' '; 11];
+    for (i, db) in name.iter_mut().enumerate() {
+      *db = self.peek(fcb + 1 + i) & 0x7F;
+    }
+    name
+  }
+
+  /// Host file for the name in an FCB, matched without regard to case
+  fn host_path(&self, fcb: usize) -> PathBuf {
+    let name = self.fcb_filename(fcb);
+    self.directory()
+      .into_iter()
+      .find(|(entry, _)| *entry == name)
+      .map(|(_, path)| path)
+      .unwrap_or_else(|| self.dir.join(host_name(&name)))
+  }
+
+  /// Files in the host directory that have a valid 8.3 name
+  fn directory(&self) -> Vec<([u8; 11], PathBuf)> {
+    let mut entries: Vec<([u8; 11], PathBuf)> = fs::read_dir(&self.dir)
+      .into_iter()
+      .flatten()
+      .flatten()
+      .filter(|entry| entry.file_type().map(|t| t.is_file()).unwrap_or(false))
+      .filter_map(|entry| entry.file_name().to_str().and_then(valid_name).map(|name| (name, entry.path())))
+      .collect();
+    entries.sort();
+    entries
+  }
+
+  fn open(&mut self, fcb: usize) -> u16 {
+    match fs::metadata(self.host_path(fcb)) {
+      Ok(metadata) => {
+        let extent = self.peek(fcb + 12) as u64;
+        let records = (metadata.len() as usize).div_ceil(RECORD_SIZE) as u64;
+        let rc = records.saturating_sub(extent * RECORD_SIZE as u64).min(RECORD_SIZE as u64);
+        self.poke(fcb + 15, rc as u8);
+        0x00
+      },
+      Err(_) => 0xFF,
+    }
+  }
+
+  fn make(&mut self, fcb: usize) -> u16 {
+    match File::create(self.host_path(fcb)) {
+      Ok(_) => {
+        self.poke(fcb + 15, 0);
+        0x00
+      },
+      Err(_) => 0xFF,
+    }
+  }
+
+  fn delete(&mut self, fcb: usize) -> u16 {
+    let pattern = self.fcb_filename(fcb);
+    let mut ret = 0xFF;
+    for (_, path) in self.directory().iter().filter(|(entry, _)| matches(&pattern, entry)) {
+      if fs::remove_file(path).is_ok() {
+        ret = 0x00;
+      }
+    }
+    ret
+  }
+
+  fn rename(&mut self, fcb: usize) -> u16 {
+    let to = self.dir.join(host_name(&self.fcb_filename(fcb + 16)));
+    match fs::rename(self.host_path(fcb), to) {
+      Ok(_) => 0x00,
+      Err(_) => 0xFF,
+    }
+  }
+
+  fn search_first(&mut self, fcb: usize) -> u16 {
+    let pattern = if self.peek(fcb) == b'?' {
+      [b'?'; 11]
+    } else {
+      self.fcb_filename(fcb)
+    };
+    self.search = self.directory()
+      .into_iter()
+      .map(|(entry, _)| entry)
+      .filter(|entry| matches(&pattern, entry))
+      .rev()
+      .collect();
+    self.search_next()
+  }
+
+  /// Write the next matching directory entry to the DMA buffer
+  fn search_next(&mut self) -> u16 {
+    match self.search.pop() {
+      Some(entry) => {
+        for i in 0..32 {
+          self.poke(self.dma + i, 0);
+        }
+        for (i, &db) in entry.iter().enumerate() {
+          self.poke(self.dma + 1 + i, db);
+        }
+        0x00
+      },
+      None => 0xFF,
+    }
+  }
+
+  /// Record addressed by the extent and current record fields
+  fn sequential_record(&self, fcb: usize) -> usize {
+    self.peek(fcb + 12) as usize * RECORD_SIZE + self.peek(fcb + 32) as usize
+  }
+
+  fn advance_record(&mut self, fcb: usize) {
+    let record = self.sequential_record(fcb) + 1;
+    self.poke(fcb + 12, (record / RECORD_SIZE) as u8);
+    self.poke(fcb + 32, (record % RECORD_SIZE) as u8);
+  }
+
+  fn random_record(&self, fcb: usize) -> usize {
+    self.peek(fcb + 33) as usize |
+      (self.peek(fcb + 34) as usize) << 8 |
+      (self.peek(fcb + 35) as usize) << 16
+  }
+
+  fn set_random_record(&mut self, fcb: usize, record: usize) {
+    self.poke(fcb + 33, record as u8);
+    self.poke(fcb + 34, (record >> 8) as u8);
+    self.poke(fcb + 35, (record >> 16) as u8);
+  }
+
+  /// Read a record into the DMA buffer, padding a short final record with EOF
+  fn read_record(&mut self, fcb: usize, record: usize) -> u16 {
+    let mut buffer = [EOF; RECORD_SIZE];
+    let read = File::open(self.host_path(fcb)).and_then(|mut file| {
+      file.seek(SeekFrom::Start((record * RECORD_SIZE) as u64))?;
+      let mut len = 0;
+      while len < RECORD_SIZE {
+        match file.read(&mut buffer[len..])? {
+          0 => break,
+          n => len += n,
+        }
+      }
+      Ok(len)
+    });
+    match read {
+      Ok(0) => 0x01,
+      Ok(_) => {
+        for (i, &db) in buffer.iter().enumerate() {
+          self.poke(self.dma + i, db);
+        }
+        0x00
+      },
+      Err(_) => 0x01,
+    }
+  }
+
+  fn write_record(&mut self, fcb: usize, record: usize) -> u16 {
+    let buffer: Vec<u8> = (0..RECORD_SIZE).map(|i| self.peek(self.dma + i)).collect();
+    let written = OpenOptions::new().write(true).open(self.host_path(fcb)).and_then(|mut file| {
+      file.seek(SeekFrom::Start((record * RECORD_SIZE) as u64))?;
+      file.write_all(&buffer)
+    });
+    match written {
+      Ok(_) => 0x00,
+      Err(_) => 0x02,
+    }
+  }
+
+  fn read_sequential(&mut self, fcb: usize) -> u16 {
+    let ret = self.read_record(fcb, self.sequential_record(fcb));
+    if ret == 0x00 {
+      self.advance_record(fcb);
+    }
+    ret
+  }
+
+  fn write_sequential(&mut self, fcb: usize) -> u16 {
+    let ret = self.write_record(fcb, self.sequential_record(fcb));
+    if ret == 0x00 {
+      self.advance_record(fcb);
+    }
+    ret
+  }
+
+  fn read_random(&mut self, fcb: usize) -> u16 {
+    let record = self.random_record(fcb);
+    self.poke(fcb + 12, (record / RECORD_SIZE) as u8);
+    self.poke(fcb + 32, (record % RECORD_SIZE) as u8);
+    self.read_record(fcb, record)
+  }
+
+  fn write_random(&mut self, fcb: usize) -> u16 {
+    let record = self.random_record(fcb);
+    self.poke(fcb + 12, (record / RECORD_SIZE) as u8);
+    self.poke(fcb + 32, (record % RECORD_SIZE) as u8);
+    self.write_record(fcb, record)
+  }
+
+  fn file_size(&mut self, fcb: usize) -> u16 {
+    match fs::metadata(self.host_path(fcb)) {
+      Ok(metadata) => {
+        self.set_random_record(fcb, (metadata.len() as usize).div_ceil(RECORD_SIZE));
+        0x00
+      },
+      Err(_) => 0xFF,
+    }
+  }
+}
+
+/// Space padded 8.3 name in an FCB, from a command line argument
+fn fcb_name(arg: &str) -> [u8; 11] {
+  let mut name = [b' '; 11];
+  let arg = arg.to_uppercase();
+  let arg = arg.split_once(':').map(|(_, file)| file).unwrap_or(&arg);
+  let (base, ext) = arg.split_once('.').unwrap_or((arg, ""));
+  let (base_field, ext_field) = name.split_at_mut(8);
+  for (field, part) in [(base_field, base), (ext_field, ext)] {
+    for (i, c) in part.bytes().take(field.len()).enumerate() {
+      if c == b'*' {
+        field[i..].fill(b'?');
+        break;
+      }
+      field[i] = c;
+    }
+  }
+  name
+}
+
+/// FCB name for a host file, if it fits in 8.3
+fn valid_name(file: &str) -> Option<[u8; 11]> {
+  let (base, ext) = file.split_once('.').unwrap_or((file, ""));
+  let valid = |part: &str, len: usize| {
+    part.len() <= len && part.bytes().all(|c| c.is_ascii_graphic() && !b".:*?".contains(&c))
+  };
+  if !base.is_empty() && valid(base, 8) && valid(ext, 3) {
+    Some(fcb_name(file))
+  } else {
+    None
+  }
+}
+
+/// Host file name for a space padded 8.3 FCB name
+fn host_name(name: &[u8; 11]) -> String {
+  let base = String::from_utf8_lossy(&name[..8]).trim_end().to_string();
+  let ext = String::from_utf8_lossy(&name[8..]).trim_end().to_string();
+  if ext.is_empty() {
+    base
+  } else {
+    format!("{}.{}", base, ext)
+  }
+}
+
+/// Compare a name against a pattern where '?' matches any character
+fn matches(pattern: &[u8; 11], name: &[u8; 11]) -> bool {
+  pattern.iter().zip(name.iter()).all(|(p, n)| *p == b'?' || p.eq_ignore_ascii_case(n))
+}
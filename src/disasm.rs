@@ -0,0 +1,231 @@
+use std::fmt;
+
+use crate::bus::Bus;
+use crate::processor::{BRANCH_CYCLES, CYCLES};
+
+const REGS: [&str; 8] = ["B", "C", "D", "E", "H", "L", "M", "A"];
+const PAIRS: [&str; 4] = ["B", "D", "H", "SP"];
+const STACK_PAIRS: [&str; 4] = ["B", "D", "H", "PSW"];
+const ALU: [&str; 8] = ["ADD", "ADC", "SUB", "SBB", "ANA", "XRA", "ORA", "CMP"];
+const ALU_IMM: [&str; 8] = ["ADI", "ACI", "SUI", "SBI", "ANI", "XRI", "ORI", "CPI"];
+const JUMPS: [&str; 8] = ["JNZ", "JZ", "JNC", "JC", "JPO", "JPE", "JP", "JM"];
+const CALLS: [&str; 8] = ["CNZ", "CZ", "CNC", "CC", "CPO", "CPE", "CP", "CM"];
+const RETURNS: [&str; 8] = ["RNZ", "RZ", "RNC", "RC", "RPO", "RPE", "RP", "RM"];
+
+/// Operand of a decoded instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+  /// Register, or M for the byte at HL
+  Reg(&'static str),
+  /// Register pair, B D H SP or PSW
+  Pair(&'static str),
+  Imm8(u8),
+  Imm16(u16),
+  /// Memory address of LDA, STA, LHLD, SHLD, jumps and calls
+  Addr(u16),
+  Port(u8),
+  /// Restart vector number
+  Rst(u8),
+}
+
+impl fmt::Display for Operand {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      Operand::Reg(name) | Operand::Pair(name) => write!(f, "{}", name),
+      Operand::Imm8(db) | Operand::Port(db) => write!(f, "{}", hex8(db)),
+      Operand::Imm16(hblb) | Operand::Addr(hblb) => write!(f, "{}", hex16(hblb)),
+      Operand::Rst(n) => write!(f, "{}", n),
+    }
+  }
+}
+
+/// Where execution continues after an instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flow {
+  /// Falls through to the next instruction
+  Next,
+  /// Unconditional JMP
+  Jump(u16),
+  /// Conditional jump, to the target or the next instruction
+  Branch(u16),
+  /// CALL or RST, returning to the next instruction
+  Call(u16),
+  /// Conditional call
+  CondCall(u16),
+  Return,
+  /// Conditional return, or the next instruction
+  CondReturn,
+  /// PCHL, target only known at run time
+  Indirect,
+  Halt,
+}
+
+/// A single decoded instruction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instruction {
+  pub address: u16,
+  pub bytes: Vec<u8>,
+  pub mnemonic: &'static str,
+  pub operands: Vec<Operand>,
+  /// Cycles when a conditional CALL or RET is not taken, or the only count
+  pub cycles: u8,
+  /// Cycles when a conditional CALL or RET is taken
+  pub taken_cycles: u8,
+  pub flow: Flow,
+  /// False for the undocumented aliases of NOP, JMP, RET and CALL
+  pub documented: bool,
+}
+
+impl Instruction {
+  pub fn opcode(&self) -> u8 {
+    self.bytes[0]
+  }
+
+  pub fn len(&self) -> usize {
+    self.bytes.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.bytes.is_empty()
+  }
+
+  /// Address of the following instruction
+  pub fn next(&self) -> u16 {
+    self.address.wrapping_add(self.len() as u16)
+  }
+
+  /// Branch, jump or call target, if it is known statically
+  pub fn target(&self) -> Option<u16> {
+    match self.flow {
+      Flow::Jump(hblb) | Flow::Branch(hblb) | Flow::Call(hblb) | Flow::CondCall(hblb) => Some(hblb),
+      _ => None,
+    }
+  }
+
+  /// Addresses execution can continue at, in fall-through then target order
+  pub fn successors(&self) -> Vec<u16> {
+    match self.flow {
+      Flow::Next | Flow::CondReturn => vec![self.next()],
+      Flow::Jump(hblb) => vec![hblb],
+      Flow::Branch(hblb) | Flow::Call(hblb) | Flow::CondCall(hblb) => vec![self.next(), hblb],
+      Flow::Return | Flow::Indirect | Flow::Halt => Vec::new(),
+    }
+  }
+
+  /// Operands in Intel syntax, e.g. `A,80H`
+  pub fn operand_text(&self) -> String {
+    self.operands.iter().map(|operand| operand.to_string()).collect::<Vec<String>>().join(",")
+  }
+}
+
+impl fmt::Display for Instruction {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    if self.operands.is_empty() {
+      write!(f, "{}", self.mnemonic)
+    } else {
+      write!(f, "{} {}", self.mnemonic, self.operand_text())
+    }
+  }
+}
+
+/// Decode the instruction at `address`
+pub fn decode<B: Bus>(bus: &B, address: u16) -> Instruction {
+  let opcode = bus.read(address);
+  let db = bus.read(address.wrapping_add(1));
+  let hblb = (bus.read(address.wrapping_add(2)) as u16) << 8 | db as u16;
+  let d = ((opcode >> 3) & 0b111) as usize;
+  let s = (opcode & 0b111) as usize;
+  let rp = ((opcode >> 4) & 0b11) as usize;
+
+  use Operand::*;
+  let (mnemonic, operands, flow): (&'static str, Vec<Operand>, Flow) = match opcode {
+    0x00 | 0x08 | 0x10 | 0x18 | 0x20 | 0x28 | 0x30 | 0x38 => ("NOP", vec![], Flow::Next),
+    0x01 | 0x11 | 0x21 | 0x31 => ("LXI", vec![Pair(PAIRS[rp]), Imm16(hblb)], Flow::Next),
+    0x02 | 0x12 => ("STAX", vec![Pair(PAIRS[rp])], Flow::Next),
+    0x03 | 0x13 | 0x23 | 0x33 => ("INX", vec![Pair(PAIRS[rp])], Flow::Next),
+    0x04 | 0x0C | 0x14 | 0x1C | 0x24 | 0x2C | 0x34 | 0x3C => ("INR", vec![Reg(REGS[d])], Flow::Next),
+    0x05 | 0x0D | 0x15 | 0x1D | 0x25 | 0x2D | 0x35 | 0x3D => ("DCR", vec![Reg(REGS[d])], Flow::Next),
+    0x06 | 0x0E | 0x16 | 0x1E | 0x26 | 0x2E | 0x36 | 0x3E => ("MVI", vec![Reg(REGS[d]), Imm8(db)], Flow::Next),
+    0x07 => ("RLC", vec![], Flow::Next),
+    0x09 | 0x19 | 0x29 | 0x39 => ("DAD", vec![Pair(PAIRS[rp])], Flow::Next),
+    0x0A | 0x1A => ("LDAX", vec![Pair(PAIRS[rp])], Flow::Next),
+    0x0B | 0x1B | 0x2B | 0x3B => ("DCX", vec![Pair(PAIRS[rp])], Flow::Next),
+    0x0F => ("RRC", vec![], Flow::Next),
+    0x17 => ("RAL", vec![], Flow::Next),
+    0x1F => ("RAR", vec![], Flow::Next),
+    0x22 => ("SHLD", vec![Addr(hblb)], Flow::Next),
+    0x27 => ("DAA", vec![], Flow::Next),
+    0x2A => ("LHLD", vec![Addr(hblb)], Flow::Next),
+    0x2F => ("CMA", vec![], Flow::Next),
+    0x32 => ("STA", vec![Addr(hblb)], Flow::Next),
+    0x37 => ("STC", vec![], Flow::Next),
+    0x3A => ("LDA", vec![Addr(hblb)], Flow::Next),
+    0x3F => ("CMC", vec![], Flow::Next),
+    0x76 => ("HLT", vec![], Flow::Halt),
+    0x40..=0x7F => ("MOV", vec![Reg(REGS[d]), Reg(REGS[s])], Flow::Next),
+    0x80..=0xBF => (ALU[d], vec![Reg(REGS[s])], Flow::Next),
+    0xC0 | 0xC8 | 0xD0 | 0xD8 | 0xE0 | 0xE8 | 0xF0 | 0xF8 => (RETURNS[d], vec![], Flow::CondReturn),
+    0xC1 | 0xD1 | 0xE1 | 0xF1 => ("POP", vec![Pair(STACK_PAIRS[rp])], Flow::Next),
+    0xC2 | 0xCA | 0xD2 | 0xDA | 0xE2 | 0xEA | 0xF2 | 0xFA => (JUMPS[d], vec![Addr(hblb)], Flow::Branch(hblb)),
+    0xC3 | 0xCB => ("JMP", vec![Addr(hblb)], Flow::Jump(hblb)),
+    0xC4 | 0xCC | 0xD4 | 0xDC | 0xE4 | 0xEC | 0xF4 | 0xFC => (CALLS[d], vec![Addr(hblb)], Flow::CondCall(hblb)),
+    0xC5 | 0xD5 | 0xE5 | 0xF5 => ("PUSH", vec![Pair(STACK_PAIRS[rp])], Flow::Next),
+    0xC6 | 0xCE | 0xD6 | 0xDE | 0xE6 | 0xEE | 0xF6 | 0xFE => (ALU_IMM[d], vec![Imm8(db)], Flow::Next),
+    0xC7 | 0xCF | 0xD7 | 0xDF | 0xE7 | 0xEF | 0xF7 | 0xFF => ("RST", vec![Rst(d as u8)], Flow::Call((d as u16) << 3)),
+    0xC9 | 0xD9 => ("RET", vec![], Flow::Return),
+    0xCD | 0xDD | 0xED | 0xFD => ("CALL", vec![Addr(hblb)], Flow::Call(hblb)),
+    0xD3 => ("OUT", vec![Port(db)], Flow::Next),
+    0xDB => ("IN", vec![Port(db)], Flow::Next),
+    0xE3 => ("XTHL", vec![], Flow::Next),
+    0xE9 => ("PCHL", vec![], Flow::Indirect),
+    0xEB => ("XCHG", vec![], Flow::Next),
+    0xF3 => ("DI", vec![], Flow::Next),
+    0xF9 => ("SPHL", vec![], Flow::Next),
+    0xFB => ("EI", vec![], Flow::Next),
+  };
+
+  let len = length(opcode);
+  let bytes = (0..len).map(|i| bus.read(address.wrapping_add(i as u16))).collect();
+  let cycles = CYCLES[opcode as usize];
+  let taken_cycles = match flow {
+    Flow::CondCall(_) | Flow::CondReturn => cycles + BRANCH_CYCLES,
+    _ => cycles,
+  };
+  let documented = !matches!(opcode, 0x08 | 0x10 | 0x18 | 0x20 | 0x28 | 0x30 | 0x38 | 0xCB | 0xD9 | 0xDD | 0xED | 0xFD);
+
+  Instruction { address, bytes, mnemonic, operands, cycles, taken_cycles, flow, documented }
+}
+
+/// Instruction length in bytes for an opcode
+pub fn length(opcode: u8) -> usize {
+  match opcode {
+    0x01 | 0x11 | 0x21 | 0x31 | 0x22 | 0x2A | 0x32 | 0x3A |
+    0xC2 | 0xC3 | 0xC4 | 0xCA | 0xCB | 0xCC | 0xCD |
+    0xD2 | 0xD4 | 0xDA | 0xDC | 0xDD |
+    0xE2 | 0xE4 | 0xEA | 0xEC | 0xED |
+    0xF2 | 0xF4 | 0xFA | 0xFC | 0xFD => 3,
+    0x06 | 0x0E | 0x16 | 0x1E | 0x26 | 0x2E | 0x36 | 0x3E |
+    0xC6 | 0xCE | 0xD3 | 0xD6 | 0xDB | 0xDE | 0xE6 | 0xEE | 0xF6 | 0xFE => 2,
+    _ => 1,
+  }
+}
+
+/// 8-bit constant in Intel syntax, e.g. `80H` or `0FFH`
+pub fn hex8(db: u8) -> String {
+  let text = format!("{:02X}H", db);
+  if text.starts_with(|c: char| c.is_ascii_alphabetic()) {
+    format!("0{}", text)
+  } else {
+    text
+  }
+}
+
+/// 16-bit constant in Intel syntax, e.g. `18D4H` or `0C3A0H`
+pub fn hex16(hblb: u16) -> String {
+  let text = format!("{:04X}H", hblb);
+  if text.starts_with(|c: char| c.is_ascii_alphabetic()) {
+    format!("0{}", text)
+  } else {
+    text
+  }
+}
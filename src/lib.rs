@@ -1,30 +1,417 @@
 use std::{env, fs, thread};
+use std::path::{Path, PathBuf};
 use std::sync::mpsc::{self, Sender, Receiver};
 use std::sync::{Arc, Mutex};
 pub mod processor;
+pub mod asm;
+pub mod bus;
+pub mod disasm;
 pub mod machine;
 pub mod memory;
 pub mod screen;
+pub mod cpm;
+pub mod trace;
+pub mod cfg;
+pub mod symbols;
+pub mod romset;
+pub mod loader;
+pub mod patch;
+pub mod shift_register;
+pub mod cabinet;
+pub mod config;
+pub mod watchdog;
+pub mod sound;
 
+use cabinet::DipSwitches;
+use cfg::Cfg;
+use config::Config;
+use cpm::Cpm;
 use machine::{Machine, KeycodeState};
+use loader::Binary;
 use memory::{ROM_SIZE, VIDEO_SIZE};
 use screen::{Resolution, Screen};
+use symbols::Symbols;
+use trace::{Image, Trace};
 
 pub fn start(mut args: env::Args) {
   args.next();
-  if let Some(filename) = args.next() {
-    let (tx, rx): (Sender<KeycodeState>, Receiver<KeycodeState>)  = mpsc::channel();
-    let screen_video: Arc<Mutex<[u8; VIDEO_SIZE]>> = Arc::new(Mutex::new([0x0; VIDEO_SIZE]));
-    let machine_video = Arc::clone(&screen_video);
-    let rom: [u8; ROM_SIZE] = fs::read(filename).unwrap()[0..ROM_SIZE].try_into().unwrap();
-    let debug = args.next().unwrap_or(String::from("-r"));
-    thread::spawn(move || {
-      if debug == "-d" {
-        Machine::new(rom, machine_video).run_debug(rx);
-      } else {
-        Machine::new(rom, machine_video).run(rx);
+  match args.next().as_deref() {
+    Some("cpm") => start_cpm(args),
+    Some("disasm") => start_disasm(args),
+    Some("asm") => start_asm(args),
+    Some("cfg") => start_cfg(args),
+    Some(filename) => start_invaders(filename, args),
+    None => (),
+  }
+}
+
+/// Play Space Invaders: `ROM... [-d] [--symbols FILE] [--log-memory]
+/// [--log-ports] [--no-verify] [--patch FILE]... [--load ADDR]
+/// [--entry ADDR] [--config FILE] [--ships N] [--extra-ship 1000|1500]
+/// [--coin-info on|off] [--no-watchdog] [--samples DIR] [--no-sound]`. The ROM is a combined image, a
+/// directory or zip holding the chip files, or the chip files themselves,
+/// checked against the known sets unless `--no-verify` is given. IPS and
+/// BPS patches are applied in order on top of the checked ROM. An Intel HEX
+/// file, or a raw binary given `--load`, is loaded at its own address
+/// instead, and `--entry` sets where it starts. The DIP switches come from
+/// the config file, then the switches given. Sound samples are read from
+/// `--samples`, the config file or `samples`. `-d` steps through the
+/// debugger, which names addresses from the bundled symbol map unless
+/// another file is given. `--log-memory` reports ROM writes and unmapped
+/// reads, and `--log-ports` the IN and OUT ports nothing handles.
+fn start_invaders(filename: &str, mut args: env::Args) {
+  let (tx, rx): (Sender<KeycodeState>, Receiver<KeycodeState>)  = mpsc::channel();
+  let screen_video: Arc<Mutex<[u8; VIDEO_SIZE]>> = Arc::new(Mutex::new([0x0; VIDEO_SIZE]));
+  let machine_video = Arc::clone(&screen_video);
+  let mut paths = vec![PathBuf::from(filename)];
+  let mut debug = false;
+  let mut log_memory = false;
+  let mut log_ports = false;
+  let mut watchdog = true;
+  let mut samples = None;
+  let mut sound = true;
+  let mut verify = true;
+  let mut load = None;
+  let mut entry = None;
+  let mut patches = Vec::new();
+  let mut config = Config::default();
+  let mut dips = Vec::new();
+  let mut symbols = Symbols::space_invaders();
+  while let Some(arg) = args.next() {
+    match arg.as_str() {
+      "-d" => debug = true,
+      "--log-memory" => log_memory = true,
+      "--log-ports" => log_ports = true,
+      "--no-watchdog" => watchdog = false,
+      "--samples" => samples = args.next().map(PathBuf::from),
+      "--no-sound" => sound = false,
+      "--no-verify" => verify = false,
+      "--patch" => patches.extend(args.next()),
+      "--config" => if let Some(path) = args.next() {
+        match Config::load(Path::new(&path)) {
+          Ok(loaded) => config = loaded,
+          Err(e) => {
+            eprintln!("{}: {}", path, e);
+            return;
+          },
+        }
+      },
+      "--ships" | "--extra-ship" | "--coin-info" => dips.push((arg, args.next().unwrap_or_default())),
+      "--load" => match address_arg(&arg, args.next()) {
+        Some(hblb) => load = Some(hblb),
+        None => return,
+      },
+      "--entry" => match address_arg(&arg, args.next()) {
+        Some(hblb) => entry = Some(hblb),
+        None => return,
+      },
+      "--symbols" => if let Some(path) = args.next() {
+        match load_symbols(&path) {
+          Some(loaded) => symbols = loaded,
+          None => return,
+        }
+      },
+      _ => paths.push(PathBuf::from(arg)),
+    }
+  }
+  for (switch, value) in dips {
+    if let Err(e) = set_dip_switch(&mut config.dips, &switch, &value) {
+      eprintln!("{}: {}", switch, e);
+      return;
+    }
+  }
+  let program = load.is_some() || paths.iter().any(|path| loader::is_hex(path));
+  let (mut rom, mut binary) = if program {
+    let mut binary = Binary::default();
+    for path in &paths {
+      match Binary::load(path, load.unwrap_or(0)) {
+        Ok(loaded) => {
+          binary.entry = loaded.entry.or(binary.entry);
+          binary.segments.extend(loaded.segments);
+        },
+        Err(e) => {
+          eprintln!("{}: {}", path.display(), e);
+          return;
+        },
+      }
+    }
+    ([0x0; ROM_SIZE], binary)
+  } else {
+    match romset::load(&paths, verify) {
+      Ok(rom) => {
+        for warning in &rom.warnings {
+          eprintln!("{}", warning);
+        }
+        (rom.image, Binary::default())
+      },
+      Err(e) => {
+        eprintln!("{}", e);
+        return;
+      },
+    }
+  };
+  for path in &patches {
+    let applied = match fs::read(path) {
+      Ok(bytes) => patch::apply(&mut rom, &bytes).map_err(|e| e.to_string()),
+      Err(e) => Err(e.to_string()),
+    };
+    if let Err(e) = applied {
+      eprintln!("{}: {}", path, e);
+      return;
+    }
+  }
+  binary.entry = entry.or(binary.entry);
+  let samples = samples.or(config.samples).unwrap_or_else(|| PathBuf::from("samples"));
+  let (sound_tx, sound_rx) = mpsc::channel();
+  thread::spawn(move || {
+    let mut machine = Machine::new(rom, machine_video);
+    machine.load(&binary);
+    machine.dip_switches(config.dips);
+    machine.symbols = symbols;
+    machine.log_memory(log_memory);
+    machine.log_ports(log_ports);
+    machine.watchdog(watchdog);
+    if sound {
+      machine.sound(sound_tx);
+    }
+    if debug {
+      machine.run_debug(rx);
+    } else {
+      machine.run(rx);
+    }
+  });
+  let mut screen = Screen::new("Space Invaders", Resolution::new(224, 256, 3), screen_video);
+  // Playing for as long as the device is kept
+  let _audio = if sound {
+    match screen.audio().and_then(|audio| sound::open(&audio, &samples, sound_rx)) {
+      Ok(device) => Some(device),
+      Err(e) => {
+        eprintln!("no sound: {}", e);
+        None
+      },
+    }
+  } else {
+    None
+  };
+  screen.run(tx);
+}
+
+/// Run a CP/M program: `cpm [--dir DIR] PROGRAM.COM [ARGS...]`
+fn start_cpm(mut args: env::Args) {
+  let mut dir = PathBuf::from(".");
+  let mut program = None;
+  while let Some(arg) = args.next() {
+    match arg.as_str() {
+      "--dir" => dir = args.next().map(PathBuf::from).unwrap_or(dir),
+      _ => {
+        program = Some(arg);
+        break;
+      },
+    }
+  }
+  let Some(program) = program else {
+    eprintln!("usage: cpm [--dir DIR] PROGRAM.COM [ARGS...]");
+    return;
+  };
+  let args: Vec<String> = args.collect();
+  match fs::read(&program).and_then(|image| Cpm::new(&image, dir, &args)) {
+    Ok(mut cpm) => cpm.run(),
+    Err(e) => eprintln!("{}: {}", program, e),
+  }
+}
+
+/// Disassemble a ROM by following control flow from reset and the RST
+/// vectors: `disasm ROM [--origin ADDR] [--entry ADDR]... [--symbols FILE]
+/// [--source] [-o FILE]`. `--source` writes assembler source instead of a
+/// listing.
+fn start_disasm(mut args: env::Args) {
+  let mut origin = 0;
+  let mut source = false;
+  let mut symbols = Symbols::new();
+  let mut entries = Vec::new();
+  let mut output = None;
+  let mut rom = None;
+  while let Some(arg) = args.next() {
+    match arg.as_str() {
+      "--origin" => match address_arg(&arg, args.next()) {
+        Some(hblb) => origin = hblb,
+        None => return,
+      },
+      "--entry" => match address_arg(&arg, args.next()) {
+        Some(hblb) => entries.push(hblb),
+        None => return,
+      },
+      "--source" => source = true,
+      "--symbols" => if let Some(path) = args.next() {
+        match load_symbols(&path) {
+          Some(loaded) => symbols = loaded,
+          None => return,
+        }
+      },
+      "-o" => output = args.next(),
+      _ => rom = Some(arg),
+    }
+  }
+  let Some(rom) = rom else {
+    eprintln!("usage: disasm ROM [--origin ADDR] [--entry ADDR]... [--symbols FILE] [--source] [-o FILE]");
+    return;
+  };
+  let image = match fs::read(&rom) {
+    Ok(bytes) => Image::new(origin, bytes),
+    Err(e) => {
+      eprintln!("{}: {}", rom, e);
+      return;
+    },
+  };
+  entries.splice(0..0, Trace::vectors(origin));
+  let trace = Trace::new(&image, &entries);
+  let listing = if source { trace.source(&image, &symbols) } else { trace.listing(&image, &symbols) };
+  match output {
+    Some(path) => if let Err(e) = fs::write(&path, listing) {
+      eprintln!("{}: {}", path, e);
+    },
+    None => print!("{}", listing),
+  }
+}
+
+/// Write the control-flow graph of a ROM as Graphviz DOT: `cfg ROM
+/// [--origin ADDR] [--entry ADDR]... [--symbols FILE] [--sub ADDR|NAME]
+/// [-o FILE]`. `--sub` draws a single subroutine instead of the program.
+fn start_cfg(mut args: env::Args) {
+  let mut origin = 0;
+  let mut symbols = Symbols::new();
+  let mut entries = Vec::new();
+  let mut sub = None;
+  let mut output = None;
+  let mut rom = None;
+  while let Some(arg) = args.next() {
+    match arg.as_str() {
+      "--origin" => match address_arg(&arg, args.next()) {
+        Some(hblb) => origin = hblb,
+        None => return,
+      },
+      "--entry" => match address_arg(&arg, args.next()) {
+        Some(hblb) => entries.push(hblb),
+        None => return,
+      },
+      "--symbols" => if let Some(path) = args.next() {
+        match load_symbols(&path) {
+          Some(loaded) => symbols = loaded,
+          None => return,
+        }
+      },
+      "--sub" => sub = args.next(),
+      "-o" => output = args.next(),
+      _ => rom = Some(arg),
+    }
+  }
+  let Some(rom) = rom else {
+    eprintln!("usage: cfg ROM [--origin ADDR] [--entry ADDR]... [--symbols FILE] [--sub ADDR|NAME] [-o FILE]");
+    return;
+  };
+  let image = match fs::read(&rom) {
+    Ok(bytes) => Image::new(origin, bytes),
+    Err(e) => {
+      eprintln!("{}: {}", rom, e);
+      return;
+    },
+  };
+  entries.splice(0..0, Trace::vectors(origin));
+  let cfg = Cfg::new(&image, &entries);
+  let dot = match sub {
+    Some(sub) => match symbols.resolve(&sub).filter(|hblb| cfg.blocks.contains_key(hblb)) {
+      Some(entry) => cfg.subroutine_dot(entry, &symbols),
+      None => {
+        eprintln!("{}: no code at {}", rom, sub);
+        return;
+      },
+    },
+    None => cfg.dot(&symbols),
+  };
+  match output {
+    Some(path) => if let Err(e) = fs::write(&path, dot) {
+      eprintln!("{}: {}", path, e);
+    },
+    None => print!("{}", dot),
+  }
+}
+
+/// Assemble Intel 8080 source into a binary: `asm SOURCE [-o FILE]`
+fn start_asm(mut args: env::Args) {
+  let mut output = None;
+  let mut source = None;
+  while let Some(arg) = args.next() {
+    match arg.as_str() {
+      "-o" => output = args.next(),
+      _ => source = Some(arg),
+    }
+  }
+  let Some(source) = source else {
+    eprintln!("usage: asm SOURCE [-o FILE]");
+    return;
+  };
+  let text = match fs::read_to_string(&source) {
+    Ok(text) => text,
+    Err(e) => {
+      eprintln!("{}: {}", source, e);
+      return;
+    },
+  };
+  let program = match asm::assemble(&text) {
+    Ok(program) => program,
+    Err(e) => {
+      eprintln!("{}: {}", source, e);
+      return;
+    },
+  };
+  let path = output.unwrap_or_else(|| PathBuf::from(&source).with_extension("bin").display().to_string());
+  if let Err(e) = fs::write(&path, &program.bytes) {
+    eprintln!("{}: {}", path, e);
+  }
+}
+
+/// Apply `--ships`, `--extra-ship` or `--coin-info`
+fn set_dip_switch(dips: &mut DipSwitches, switch: &str, value: &str) -> Result<(), String> {
+  match switch {
+    "--ships" => dips.set_ships(value.parse().map_err(|_| format!("invalid number {:?}", value))?),
+    "--extra-ship" => dips.set_extra_ship_at(value.parse().map_err(|_| format!("invalid score {:?}", value))?),
+    _ => {
+      dips.coin_info = match value {
+        "on" => true,
+        "off" => false,
+        _ => return Err(format!("expected on or off, not {:?}", value)),
+      };
+      Ok(())
+    },
+  }
+}
+
+/// Symbol file, reporting why it could not be read. The caller stops, as
+/// for a bad `--config`, rather than go on without the names asked for.
+fn load_symbols(path: &str) -> Option<Symbols> {
+  match Symbols::load(Path::new(path)) {
+    Ok(symbols) => Some(symbols),
+    Err(e) => {
+      eprintln!("{}: {}", path, e);
+      None
+    },
+  }
+}
+
+/// Hexadecimal address given to `switch`, reported on stderr when missing
+/// or invalid
+fn address_arg(switch: &str, value: Option<String>) -> Option<u16> {
+  match value {
+    Some(text) => {
+      let hblb = symbols::parse_address(&text);
+      if hblb.is_none() {
+        eprintln!("{}: invalid address {}", switch, text);
       }
-    });
-    Screen::new("Space Invaders", Resolution::new(224, 256, 3), screen_video).run(tx);
+      hblb
+    },
+    None => {
+      eprintln!("{}: missing address", switch);
+      None
+    },
   }
-}
\ No newline at end of file
+}
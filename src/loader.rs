@@ -0,0 +1,119 @@
+use std::path::Path;
+use std::{fs, io};
+
+/// Bytes to place at an address
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+  pub address: u16,
+  pub bytes: Vec<u8>,
+}
+
+/// Program built by another toolchain, loaded into memory by
+/// `Processor::load`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Binary {
+  pub segments: Vec<Segment>,
+  /// Where to start running, if the file says or the user asks
+  pub entry: Option<u16>,
+}
+
+impl Binary {
+  /// Raw binary placed at `address`
+  pub fn raw(address: u16, bytes: Vec<u8>) -> Binary {
+    Binary { segments: vec![Segment { address, bytes }], entry: None }
+  }
+
+  /// Load `.hex` and `.ihx` files as Intel HEX and anything else as a raw
+  /// binary at `address`, which must end below 64 KiB
+  pub fn load(path: &Path, address: u16) -> io::Result<Binary> {
+    if is_hex(path) {
+      let text = fs::read_to_string(path)?;
+      Binary::parse_hex(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    } else {
+      let bytes = fs::read(path)?;
+      if address as usize + bytes.len() > 0x10000 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "data beyond 64 KiB"));
+      }
+      Ok(Binary::raw(address, bytes))
+    }
+  }
+
+  /// Intel HEX with data, end of file, extended segment and linear address
+  /// and start address records. Every byte must land below 64 KiB.
+  pub fn parse_hex(text: &str) -> Result<Binary, String> {
+    let mut binary = Binary::default();
+    let mut base: u32 = 0;
+    for (i, line) in text.lines().enumerate() {
+      let line = line.trim();
+      if line.is_empty() {
+        continue;
+      }
+      let error = |e: &str| format!("line {}: {}", i + 1, e);
+      let record = line.strip_prefix(':').ok_or(error("expected ':'"))?;
+      if !record.is_ascii() {
+        return Err(error("invalid hex digit"));
+      }
+      if record.len() % 2 != 0 || record.len() < 10 {
+        return Err(error("truncated record"));
+      }
+      let bytes: Vec<u8> = (0..record.len())
+        .step_by(2)
+        .map(|j| u8::from_str_radix(&record[j..j + 2], 16))
+        .collect::<Result<_, _>>()
+        .map_err(|_| error("invalid hex digit"))?;
+      let len = bytes[0] as usize;
+      if bytes.len() != len + 5 {
+        return Err(error("record length does not match its data"));
+      }
+      if bytes.iter().fold(0u8, |sum, &db| sum.wrapping_add(db)) != 0 {
+        return Err(error("bad checksum"));
+      }
+      let offset = u16::from_be_bytes([bytes[1], bytes[2]]) as u32;
+      let data = &bytes[4..4 + len];
+      let word = |data: &[u8]| u16::from_be_bytes([data[0], data[1]]) as u32;
+      match (bytes[3], len) {
+        (0x00, _) => {
+          let start = base as u64 + offset as u64;
+          if start + len as u64 > 0x10000 {
+            return Err(error("data beyond 64 KiB"));
+          }
+          binary.add(start as u16, data);
+        },
+        (0x01, _) => break,
+        (0x02, 2) => base = word(data) << 4,
+        (0x04, 2) => base = word(data) << 16,
+        (0x03, 4) => binary.entry = Some(entry((word(data) << 4) + word(&data[2..])).ok_or(error("entry beyond 64 KiB"))?),
+        (0x05, 4) => binary.entry = Some(entry((word(data) << 16) | word(&data[2..])).ok_or(error("entry beyond 64 KiB"))?),
+        (0x02..=0x05, _) => return Err(error("wrong record length")),
+        (kind, _) => return Err(error(&format!("unknown record type {:02X}", kind))),
+      }
+    }
+    Ok(binary)
+  }
+
+  /// Number of bytes in all segments
+  pub fn len(&self) -> usize {
+    self.segments.iter().map(|segment| segment.bytes.len()).sum()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  /// Append data, extending the last segment when it continues it
+  fn add(&mut self, address: u16, data: &[u8]) {
+    match self.segments.last_mut() {
+      Some(last) if last.address as usize + last.bytes.len() == address as usize => last.bytes.extend(data),
+      _ => self.segments.push(Segment { address, bytes: data.to_vec() }),
+    }
+  }
+}
+
+fn entry(address: u32) -> Option<u16> {
+  u16::try_from(address).ok()
+}
+
+/// Named like an Intel HEX file
+pub fn is_hex(path: &Path) -> bool {
+  path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("hex") || ext.eq_ignore_ascii_case("ihx"))
+}
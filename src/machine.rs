@@ -1,26 +1,33 @@
+use std::collections::BTreeSet;
 use std::io;
 use std::sync::{Arc, Mutex};
-use std::sync::mpsc::Receiver;
+use std::sync::mpsc::{Receiver, Sender};
 use sdl2::keyboard::Keycode;
 use std::thread;
-use std::time::{Duration, Instant};
+use std::time::Duration;
 
+use crate::bus::{Bus, IoBus, PortAccess};
+use crate::cabinet::{CabinetInputs, DipSwitches};
+use crate::loader::Binary;
 use crate::processor::Processor;
-use crate::memory::{ROM_SIZE, VIDEO_SIZE, VIDEO_START, MEM_SIZE};
+use crate::shift_register::ShiftRegister;
+use crate::sound::{SoundEvent, SoundLatches};
+use crate::watchdog::Watchdog;
+use crate::symbols::Symbols;
+use crate::memory::{Memory, ROM_SIZE, VIDEO_SIZE, VIDEO_START};
 //use crate::screen::SCREEN_F;
 
 const CPU_F: u32 = 2_000_000;
-const CPU_T: u32 = 1_000_000_000 / CPU_F;
 const VBLANK: u32 = CPU_F / 60;
 
 struct Counter {
-  line: Option<usize>,
+  line: Option<u16>,
   add: usize,
 }
 
 impl Counter {
-  fn valid(&self, pc: usize) -> bool {
-    self.line == Some(pc) || (self.line == None && self.add == 0)
+  fn valid(&self, pc: u16) -> bool {
+    self.line == Some(pc) || (self.line.is_none() && self.add == 0)
   }
 
   fn decrement(&mut self) {
@@ -29,20 +36,19 @@ impl Counter {
     }
   }
 
-  fn debug_cli(&mut self) {
+  /// Read a command: a hex address or symbol name to run to, `:N` to step
+  /// N instructions, anything else to step one
+  fn debug_cli(&mut self, symbols: &Symbols) {
     let mut input = String::new();
     io::stdin().read_line(&mut input).unwrap();
-    match usize::from_str_radix(input.trim(), 16) {
-      Ok(x) => self.line = Some(x),
+    match symbols.resolve(&input) {
+      Some(x) => self.line = Some(x),
       _ => {
         self.line = None;
         let mut input_iter = input.chars();
         self.add = if input_iter.next() == Some(':') {
           let input: String = input_iter.collect();
-          match usize::from_str_radix(input.trim(), 16) {
-            Ok(x) => x,
-            _ => 0
-          }
+          usize::from_str_radix(input.trim(), 16).unwrap_or_default()
         } else {
           0
         };
@@ -56,20 +62,143 @@ pub struct KeycodeState {
   pub pressed: bool,
 }
 
+/// Space Invaders I/O ports
+struct Ports {
+  inputs: CabinetInputs,
+  /// Last value written to each port
+  op: [u8; 8],
+  shift: ShiftRegister,
+  sound: SoundLatches,
+  /// Where sound events go, `None` when there is no sound
+  sound_tx: Option<Sender<SoundEvent>>,
+  /// Kicked by OUT 6, `None` when disabled
+  watchdog: Option<Watchdog>,
+  /// Ports no device answers, recorded when logging is on
+  unhandled: Option<BTreeSet<PortAccess>>,
+  /// Unhandled ports seen for the first time since the last report
+  new_unhandled: Vec<PortAccess>,
+}
+
+impl Ports {
+  /// Clear the shift register and the output latches as the reset line
+  /// does, stopping any sound still playing
+  fn reset(&mut self) {
+    self.shift = ShiftRegister::new();
+    for port in [0x3, 0x5] {
+      self.output(port, 0);
+    }
+    self.op = [0; 8];
+  }
+
+  fn unhandled(&mut self, access: PortAccess) {
+    if let Some(unhandled) = &mut self.unhandled {
+      if unhandled.insert(access) {
+        self.new_unhandled.push(access);
+      }
+    }
+  }
+}
+
+impl IoBus for Ports {
+  fn input(&mut self, port: u8) -> u8 {
+    match port {
+      0x3 => self.shift.read(),
+      _ => self.inputs.read(port).unwrap_or_else(|| {
+        self.unhandled(PortAccess::In(port));
+        0
+      }),
+    }
+  }
+
+  fn output(&mut self, port: u8, db: u8) {
+    self.op[port as usize & 0x7] = db;
+    match port {
+      0x2 => self.shift.write_offset(db),
+      0x3 | 0x5 => for event in self.sound.write(port, db) {
+        if let Some(tx) = &self.sound_tx {
+          // The audio device may have gone, play on silently
+          tx.send(event).ok();
+        }
+      },
+      0x4 => self.shift.write_data(db),
+      0x6 => if let Some(watchdog) = &mut self.watchdog {
+        watchdog.kick();
+      },
+      _ => self.unhandled(PortAccess::Out(port)),
+    }
+  }
+}
+
 pub struct Machine {
   cpu: Processor,
   video: Arc<Mutex<[u8; VIDEO_SIZE]>>,
-  ip: [u8; 8],
-  op: [u8; 8],
+  io: Ports,
+  /// Names shown and accepted by the debugger
+  pub symbols: Symbols,
 }
 
 impl Machine {
   pub fn new(rom: [u8; ROM_SIZE], video: Arc<Mutex<[u8; VIDEO_SIZE]>>) -> Machine {
     Machine {
-      cpu: Processor::new(rom),
+      cpu: Processor::new(Memory::new(rom)),
       video,
-      ip: [0; 8],
-      op: [0; 8],
+      io: Ports {
+        inputs: CabinetInputs::new(),
+        op: [0; 8],
+        shift: ShiftRegister::new(),
+        sound: SoundLatches::new(),
+        sound_tx: None,
+        watchdog: Some(Watchdog::default()),
+        unhandled: None,
+        new_unhandled: Vec::new(),
+      },
+      symbols: Symbols::new(),
+    }
+  }
+
+  /// Load a program on top of the ROM, for homebrew and test snippets
+  pub fn load(&mut self, binary: &Binary) {
+    self.cpu.load(binary);
+  }
+
+  /// Set the DIP switch bank read through port 2
+  pub fn dip_switches(&mut self, dips: DipSwitches) {
+    self.io.inputs.dips = dips;
+  }
+
+  /// Report ROM writes and unmapped reads on stderr once a frame
+  pub fn log_memory(&mut self, on: bool) {
+    self.cpu.mem.set_logging(on);
+  }
+
+  /// Send the sounds started and stopped through ports 3 and 5
+  pub fn sound(&mut self, tx: Sender<SoundEvent>) {
+    self.io.sound_tx = Some(tx);
+  }
+
+  /// Reset the machine when the program stops writing to port 6, on by
+  /// default
+  pub fn watchdog(&mut self, on: bool) {
+    self.io.watchdog = if on { Some(Watchdog::default()) } else { None };
+  }
+
+  /// Report IN and OUT ports no device handles on stderr, each the first
+  /// time it is used
+  pub fn log_ports(&mut self, on: bool) {
+    self.io.unhandled = if on { Some(BTreeSet::new()) } else { None };
+  }
+
+  /// Ports used that no device handles, empty unless logging is on
+  pub fn unhandled_ports(&self) -> Vec<PortAccess> {
+    self.io.unhandled.iter().flatten().copied().collect()
+  }
+
+  /// Pull the RESET line, as the watchdog does
+  pub fn reset(&mut self) {
+    self.cpu.reset();
+    self.io.reset();
+    if let Some(watchdog) = &mut self.io.watchdog {
+      watchdog.kick();
     }
   }
 
@@ -79,8 +208,7 @@ impl Machine {
     let mut screen_end = 1;
 
     loop {
-      let start = Instant::now();
-      if counter <= 0 {
+      if counter == 0 {
         if let Ok(keycode) = rx.try_recv() {
           self.key_state_change(keycode);
         }
@@ -89,7 +217,7 @@ impl Machine {
         counter -= 1;
       }
       thread::sleep(Duration::new(0, 200));
-      if video_counter <= 0 {
+      if video_counter == 0 {
         self.map_video(screen_end);
         screen_end = if screen_end == 1 { 2 } else { 1 };
         video_counter = VBLANK;
@@ -105,19 +233,18 @@ impl Machine {
     let mut debug: Counter = Counter { line: None, add: 0 };
     let mut screen_end = 1;
     loop {
-      if counter <= 0 {
+      if counter == 0 {
         if let Ok(keycode) = rx.try_recv() {
           self.key_state_change(keycode);
         }
         counter = self.exec();
 
-        if debug.add > 0 || debug.line == None {
+        if debug.add > 0 || debug.line.is_none() {
           self.print();
         }
-        if debug.valid(self.cpu.pc) {
-          //println!("{:?}", self.cpu.mem.ram);
+        if debug.valid(self.cpu.state().pc) {
           self.map_video(0);
-          debug.debug_cli();
+          debug.debug_cli(&self.symbols);
         } else {
           debug.decrement();
         }
@@ -125,7 +252,7 @@ impl Machine {
       } else {
         counter -= 1;
       }
-      if video_counter <= 0 {
+      if video_counter == 0 {
         self.map_video(screen_end);
         screen_end = if screen_end == 1 { 2 } else { 1 };
         video_counter = VBLANK;
@@ -136,37 +263,28 @@ impl Machine {
   }
   
   pub fn exec(&mut self) -> usize {
-    let opcode = self.cpu.mem.read(self.cpu.pc);
-    let port = self.cpu.mem.read(self.cpu.pc+1);
-
-    match opcode {
-      0xDB => {
-        self.cpu.a = self.input(port);
-        self.cpu.pc += 2;
-        10
-      },
-      0xD3 => {
-        self.output(port, self.cpu.a);
-        self.cpu.pc += 2;
-        10
-      },
-      _ => self.cpu.exec(),
-    }
+    self.cpu.exec(&mut self.io)
   }
 
   fn map_video(&mut self, screen_end: usize) {
     if screen_end > 0 {
-      self.cpu.int(screen_end);
+      self.cpu.rst(screen_end as u8);
+    }
+    for fault in self.cpu.mem.take_log() {
+      eprintln!("{}", fault);
+    }
+    for access in self.io.new_unhandled.split_off(0) {
+      eprintln!("unhandled {}", access);
+    }
+    // RST 2 is the vblank interrupt, RST 1 comes mid-screen. The watchdog
+    // counts whole frames: the ROM can go over 400 interrupts between kicks.
+    if screen_end == 2 && self.io.watchdog.as_mut().is_some_and(|watchdog| watchdog.frame()) {
+      eprintln!("watchdog reset at {:04X}", self.cpu.state().pc);
+      self.reset();
     }
-    /*if self.cpu.ie {
-      println!("Interrupt");
-      self.cpu.print();
-      let mut input = String::new();
-      io::stdin().read_line(&mut input).unwrap();
-    }*/
     if let Ok(ref mut video) = self.video.try_lock() {
       for i in 0..VIDEO_SIZE {
-          video[i] = self.cpu.mem.read(VIDEO_START+i);
+          video[i] = self.cpu.mem.read((VIDEO_START + i) as u16);
       }
     }
   }
@@ -174,51 +292,30 @@ impl Machine {
   
 
   pub fn print(&self) {
-    self.cpu.print();
-    println!("IP:\t{:?}", self.ip);
-    println!("OP:\t{:?}", self.op);
-  }
-
-  fn input(&mut self, port: u8) -> u8 {
-    match port {
-      0x3 => ((((self.op[4] as u16) << 8) | self.ip[3] as u16) >> (8 - self.op[2])) as u8,
-      _ => self.cpu.a,
-    }
-  }
-
-  fn output(&mut self, port: u8, db: u8) {
-    match port {
-      0x2 => self.op[2] = db & 0x7,
-      0x4 => {
-        self.ip[3] = self.op[4];
-        self.op[4] = db;
-      },
-      _ => (),
-    }
+    self.cpu.print(&self.symbols);
+    let inputs = &self.io.inputs;
+    println!("IN:\t{:02X} {:02X} {:02X}", inputs.port0(), inputs.port1(), inputs.port2());
+    println!("OP:\t{:?}", self.io.op);
+    println!("SHIFT:\t{:04X} offset {}", self.io.shift.value(), self.io.shift.offset());
   }
 
+  /// C coin, 1 and 2 start, Space/Left/Right player 1, W/A/D player 2,
+  /// T tilt
   fn key_state_change(&mut self, state: KeycodeState) {
-    if state.pressed {
-      match state.code {
-        Keycode::C => self.ip[1] |= 0x01,
-        Keycode::X => self.ip[1] |= 0x02,
-        Keycode::Z => self.ip[1] |= 0x04,
-        Keycode::Space => self.ip[1] |= 0x10,
-        Keycode::Left => self.ip[1] |= 0x20,
-        Keycode::Right => self.ip[1] |= 0x40,
-        _ => (),
-      }
-    } else {
-      match state.code {
-        Keycode::C => self.ip[1] &= !0x01,
-        Keycode::X => self.ip[1] &= !0x02,
-        Keycode::Z => self.ip[1] &= !0x04,
-        Keycode::Space => self.ip[1] &= !0x10,
-        Keycode::Left => self.ip[1] &= !0x20,
-        Keycode::Right => self.ip[1] &= !0x40,
-        _ => (),
-      }
-    }
-    self.cpu.print();
+    let inputs = &mut self.io.inputs;
+    let button = match state.code {
+      Keycode::C => &mut inputs.coin,
+      Keycode::Num1 | Keycode::Z => &mut inputs.p1_start,
+      Keycode::Num2 | Keycode::X => &mut inputs.p2_start,
+      Keycode::Space => &mut inputs.p1.fire,
+      Keycode::Left => &mut inputs.p1.left,
+      Keycode::Right => &mut inputs.p1.right,
+      Keycode::W => &mut inputs.p2.fire,
+      Keycode::A => &mut inputs.p2.left,
+      Keycode::D => &mut inputs.p2.right,
+      Keycode::T => &mut inputs.tilt,
+      _ => return,
+    };
+    *button = state.pressed;
   }
 }
\ No newline at end of file
@@ -1,35 +1,258 @@
-pub const MEM_SIZE: usize = 0x4000;
+use std::cell::RefCell;
+use std::fmt;
+
+use crate::bus::Bus;
+
 pub const ROM_SIZE: usize = 0x2000;
-pub const RAM_SIZE: usize = 0x2000;
 pub const VIDEO_START: usize = 0x2400;
 pub const VIDEO_SIZE: usize = 0x1C00;
 
-#[derive(Debug)]
+/// Value read from addresses nothing drives
+pub const OPEN_BUS: u8 = 0xFF;
+
+/// Device mapped into memory by a callback region
+pub trait Handler: Send {
+  /// Read at `offset` from the start of the region, after masking
+  fn read(&self, offset: u16) -> u8;
+  fn write(&mut self, offset: u16, db: u8);
+}
+
+/// What a region of the address space is
+pub enum Kind {
+  /// Read-only, writes are dropped. Bytes past the end of the image read 0.
+  Rom(Vec<u8>),
+  Ram,
+  /// Repeats the memory at the given address
+  Mirror(u16),
+  /// Reads return the value, writes are dropped
+  OpenBus(u8),
+  Callback(Box<dyn Handler>),
+}
+
+/// Addresses `start..=end`. The offset into the region is
+/// `(address - start) & mask`, so a smaller device repeats through it.
+pub struct Region {
+  pub start: u16,
+  pub end: u16,
+  pub mask: u16,
+  pub kind: Kind,
+}
+
+/// Declarative layout of an address space. Regions are searched in order,
+/// the first one containing an address decodes it. Addresses in no region
+/// are unmapped and read as `OPEN_BUS`.
+#[derive(Default)]
+pub struct MemoryMap {
+  pub regions: Vec<Region>,
+}
+
+impl MemoryMap {
+  pub fn new() -> MemoryMap {
+    MemoryMap::default()
+  }
+
+  /// 8 KiB ROM, 8 KiB RAM including video, with RAM mirrored through
+  /// 0x4000-0xFFFF as on the Space Invaders board
+  pub fn space_invaders(rom: [u8; ROM_SIZE]) -> MemoryMap {
+    MemoryMap::new()
+      .rom(0x0000, 0x1FFF, rom.to_vec())
+      .ram(0x2000, 0x3FFF)
+      .mirror(0x4000, 0xFFFF, 0x2000).mask(0x1FFF)
+  }
+
+  /// 64 KiB of RAM and no ROM, as used by CP/M programs
+  pub fn flat() -> MemoryMap {
+    MemoryMap::new().ram(0x0000, 0xFFFF)
+  }
+
+  pub fn region(mut self, start: u16, end: u16, kind: Kind) -> MemoryMap {
+    self.regions.push(Region { start, end, mask: 0xFFFF, kind });
+    self
+  }
+
+  pub fn rom(self, start: u16, end: u16, bytes: Vec<u8>) -> MemoryMap {
+    self.region(start, end, Kind::Rom(bytes))
+  }
+
+  pub fn ram(self, start: u16, end: u16) -> MemoryMap {
+    self.region(start, end, Kind::Ram)
+  }
+
+  pub fn mirror(self, start: u16, end: u16, target: u16) -> MemoryMap {
+    self.region(start, end, Kind::Mirror(target))
+  }
+
+  pub fn open_bus(self, start: u16, end: u16, db: u8) -> MemoryMap {
+    self.region(start, end, Kind::OpenBus(db))
+  }
+
+  pub fn callback(self, start: u16, end: u16, handler: Box<dyn Handler>) -> MemoryMap {
+    self.region(start, end, Kind::Callback(handler))
+  }
+
+  /// Set the address mask of the region added last
+  pub fn mask(mut self, mask: u16) -> MemoryMap {
+    if let Some(region) = self.regions.last_mut() {
+      region.mask = mask;
+    }
+    self
+  }
+}
+
+/// Accesses that real hardware would ignore, recorded when logging is on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+  RomWrite { hblb: u16, db: u8 },
+  UnmappedRead { hblb: u16 },
+  UnmappedWrite { hblb: u16, db: u8 },
+}
+
+impl fmt::Display for Fault {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      Fault::RomWrite { hblb, db } => write!(f, "write {:02X} to ROM at {:04X}", db, hblb),
+      Fault::UnmappedRead { hblb } => write!(f, "read from unmapped {:04X}", hblb),
+      Fault::UnmappedWrite { hblb, db } => write!(f, "write {:02X} to unmapped {:04X}", db, hblb),
+    }
+  }
+}
+
+/// Storage behind a region
+enum Backing {
+  Rom(Vec<u8>),
+  Ram(Vec<u8>),
+  Mirror(u16),
+  OpenBus(u8),
+  Callback(Box<dyn Handler>),
+}
+
+struct Mapped {
+  start: u16,
+  end: u16,
+  mask: u16,
+  backing: Backing,
+}
+
+/// Address space built from a `MemoryMap`
 pub struct Memory {
-  pub rom: [u8; ROM_SIZE],
-  pub ram: [u8; RAM_SIZE],
+  regions: Vec<Mapped>,
+  log: Option<RefCell<Vec<Fault>>>,
 }
 
 impl Memory {
+  /// Space Invaders board
   pub fn new(rom: [u8; ROM_SIZE]) -> Memory {
-    Memory { rom, ram: [0x0; RAM_SIZE] }
+    Memory::from_map(MemoryMap::space_invaders(rom))
+  }
+
+  /// 64 KiB of RAM and no ROM, as used by CP/M programs
+  pub fn flat() -> Memory {
+    Memory::from_map(MemoryMap::flat())
+  }
+
+  pub fn from_map(map: MemoryMap) -> Memory {
+    let regions = map.regions.into_iter().map(|region| {
+      let size = (region.end.saturating_sub(region.start) & region.mask) as usize + 1;
+      let backing = match region.kind {
+        Kind::Rom(bytes) => Backing::Rom(bytes),
+        Kind::Ram => Backing::Ram(vec![0x0; size]),
+        Kind::Mirror(target) => Backing::Mirror(target),
+        Kind::OpenBus(db) => Backing::OpenBus(db),
+        Kind::Callback(handler) => Backing::Callback(handler),
+      };
+      Mapped { start: region.start, end: region.end, mask: region.mask, backing }
+    }).collect();
+    Memory { regions, log: None }
+  }
+
+  /// Copy bytes in from `hblb` on, wrapping at 64 KiB. Unlike writes from
+  /// the processor this also fills ROM, up to the end of its region.
+  /// Unmapped and open-bus addresses are skipped.
+  pub fn load(&mut self, hblb: u16, bytes: &[u8]) {
+    for (i, &db) in bytes.iter().enumerate() {
+      let Some((index, offset)) = self.decode(hblb.wrapping_add(i as u16)) else {
+        continue;
+      };
+      let region = &mut self.regions[index];
+      match &mut region.backing {
+        Backing::Rom(image) => {
+          let offset = offset as usize;
+          if offset >= image.len() {
+            image.resize(offset + 1, 0x0);
+          }
+          image[offset] = db;
+        },
+        Backing::Ram(ram) => if let Some(cell) = ram.get_mut(offset as usize) {
+          *cell = db;
+        },
+        Backing::Callback(handler) => handler.write(offset, db),
+        Backing::OpenBus(_) | Backing::Mirror(_) => (),
+      }
+    }
+  }
+
+  /// Start or stop recording ROM writes and unmapped accesses
+  pub fn set_logging(&mut self, on: bool) {
+    self.log = if on { Some(RefCell::new(Vec::new())) } else { None };
+  }
+
+  /// Faults recorded since the last call
+  pub fn take_log(&mut self) -> Vec<Fault> {
+    self.log.as_mut().map(|log| log.get_mut().split_off(0)).unwrap_or_default()
+  }
+
+  fn record(&self, fault: Fault) {
+    if let Some(log) = &self.log {
+      log.borrow_mut().push(fault);
+    }
+  }
+
+  /// Region decoding an address and the offset into it, following one
+  /// level of mirroring
+  fn decode(&self, hblb: u16) -> Option<(usize, u16)> {
+    let (index, offset) = self.find(hblb)?;
+    match self.regions[index].backing {
+      Backing::Mirror(target) => self.find(target.wrapping_add(offset))
+        .filter(|&(index, _)| !matches!(self.regions[index].backing, Backing::Mirror(_))),
+      _ => Some((index, offset)),
+    }
   }
 
-  /// Read from RAM and ROM
-  pub fn read(&self, hblb: usize) -> u8 {
-    if hblb < ROM_SIZE {
-      self.rom[hblb]
-    } else if hblb < MEM_SIZE {
-      self.ram[hblb - ROM_SIZE]
-    } else {
-      0
+  fn find(&self, hblb: u16) -> Option<(usize, u16)> {
+    self.regions
+      .iter()
+      .position(|region| (region.start..=region.end).contains(&hblb))
+      .map(|index| (index, (hblb - self.regions[index].start) & self.regions[index].mask))
+  }
+}
+
+impl Bus for Memory {
+  fn read(&self, hblb: u16) -> u8 {
+    let Some((index, offset)) = self.decode(hblb) else {
+      self.record(Fault::UnmappedRead { hblb });
+      return OPEN_BUS;
+    };
+    match &self.regions[index].backing {
+      Backing::Rom(bytes) | Backing::Ram(bytes) => bytes.get(offset as usize).copied().unwrap_or(0),
+      Backing::OpenBus(db) => *db,
+      Backing::Callback(handler) => handler.read(offset),
+      Backing::Mirror(_) => unreachable!(),
     }
   }
 
-  /// Only write to RAM
-  pub fn write(&mut self, hblb: usize, db: u8) {
-    if hblb >= ROM_SIZE && hblb < MEM_SIZE {
-      self.ram[hblb - ROM_SIZE] = db;
+  /// ROM and open-bus writes are dropped
+  fn write(&mut self, hblb: u16, db: u8) {
+    let Some((index, offset)) = self.decode(hblb) else {
+      self.record(Fault::UnmappedWrite { hblb, db });
+      return;
+    };
+    match &mut self.regions[index].backing {
+      Backing::Ram(bytes) => if let Some(cell) = bytes.get_mut(offset as usize) {
+        *cell = db;
+      },
+      Backing::Rom(_) => self.record(Fault::RomWrite { hblb, db }),
+      Backing::Callback(handler) => handler.write(offset, db),
+      Backing::OpenBus(_) | Backing::Mirror(_) => (),
     }
   }
-}
\ No newline at end of file
+}
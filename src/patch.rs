@@ -0,0 +1,200 @@
+use std::fmt;
+
+/// Why a patch could not be applied
+#[derive(Debug, PartialEq, Eq)]
+pub enum PatchError {
+  /// Not an IPS or BPS patch, or cut short
+  Format(String),
+  /// The patch is for a different ROM
+  SourceChecksum { crc32: u32, expected: u32 },
+  TargetChecksum { crc32: u32, expected: u32 },
+  /// The patch file itself is damaged
+  PatchChecksum { crc32: u32, expected: u32 },
+  /// The patched image would not fit the ROM
+  Size { size: usize, expected: usize },
+}
+
+impl fmt::Display for PatchError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      PatchError::Format(e) => write!(f, "{}", e),
+      PatchError::SourceChecksum { crc32, expected } => write!(f, "patch is for a ROM with CRC32 {:08x}, not {:08x}", expected, crc32),
+      PatchError::TargetChecksum { crc32, expected } => write!(f, "patched ROM has CRC32 {:08x}, expected {:08x}", crc32, expected),
+      PatchError::PatchChecksum { crc32, expected } => write!(f, "damaged patch, CRC32 {:08x}, expected {:08x}", crc32, expected),
+      PatchError::Size { size, expected } => write!(f, "patched ROM is {} bytes, expected {}", size, expected),
+    }
+  }
+}
+
+impl std::error::Error for PatchError {}
+
+/// Apply an IPS or BPS patch to `rom` in place, telling them apart by their
+/// header. The patched image must be the same size as `rom`.
+pub fn apply(rom: &mut [u8], patch: &[u8]) -> Result<(), PatchError> {
+  let patched = if patch.starts_with(b"PATCH") {
+    ips(rom, patch)?
+  } else if patch.starts_with(b"BPS1") {
+    bps(rom, patch)?
+  } else {
+    return Err(format("not an IPS or BPS patch"));
+  };
+  if patched.len() != rom.len() {
+    return Err(PatchError::Size { size: patched.len(), expected: rom.len() });
+  }
+  rom.copy_from_slice(&patched);
+  Ok(())
+}
+
+/// IPS: records of a 24-bit offset and 16-bit size followed by the data, or
+/// a zero size, 16-bit count and a byte to repeat, up to `EOF`. An optional
+/// 24-bit length after `EOF` truncates the image.
+pub fn ips(source: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+  let mut target = source.to_vec();
+  let mut reader = Reader { patch, at: 5 };
+  loop {
+    if reader.rest().starts_with(b"EOF") {
+      reader.at += 3;
+      break;
+    }
+    let offset = reader.int(3)?;
+    let size = reader.int(2)?;
+    let (data, size) = if size == 0 {
+      let count = reader.int(2)?;
+      (vec![reader.byte()?; count], count)
+    } else {
+      (reader.bytes(size)?.to_vec(), size)
+    };
+    if target.len() < offset + size {
+      target.resize(offset + size, 0x0);
+    }
+    target[offset..offset + size].copy_from_slice(&data);
+  }
+  if reader.rest().len() == 3 {
+    let len = reader.int(3)?;
+    target.truncate(len);
+  }
+  Ok(target)
+}
+
+/// BPS: varint sizes and metadata, then source and target read and copy
+/// actions, ending in the CRC32 of the source, the target and the patch.
+/// The target must be the size of the source.
+pub fn bps(source: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+  if patch.len() < 4 + 12 {
+    return Err(format("BPS patch too short"));
+  }
+  let footer = |i: usize| u32::from_le_bytes(patch[patch.len() - 12 + 4 * i..patch.len() - 8 + 4 * i].try_into().unwrap());
+  let crc32 = crc32fast::hash(&patch[..patch.len() - 4]);
+  if crc32 != footer(2) {
+    return Err(PatchError::PatchChecksum { crc32, expected: footer(2) });
+  }
+  let crc32 = crc32fast::hash(source);
+  if crc32 != footer(0) {
+    return Err(PatchError::SourceChecksum { crc32, expected: footer(0) });
+  }
+
+  let mut reader = Reader { patch: &patch[..patch.len() - 12], at: 4 };
+  let source_size = reader.number()?;
+  let target_size = reader.number()?;
+  let metadata = reader.number()?;
+  reader.bytes(metadata)?;
+  if source_size != source.len() {
+    return Err(format("BPS patch is for a ROM of a different size"));
+  }
+  // The ROM can not change size, and the size is checked before trusting it
+  if target_size != source.len() {
+    return Err(PatchError::Size { size: target_size, expected: source.len() });
+  }
+
+  let mut target = Vec::with_capacity(target_size);
+  let mut source_at: usize = 0;
+  let mut target_at: usize = 0;
+  while !reader.rest().is_empty() {
+    let action = reader.number()?;
+    let len = (action >> 2) + 1;
+    if len > target_size - target.len() {
+      return Err(format("BPS actions write past the target size"));
+    }
+    match action & 3 {
+      0 => {
+        let at = target.len();
+        target.extend(source.get(at..at + len).ok_or(format("BPS source read past the end"))?);
+      },
+      1 => target.extend(reader.bytes(len)?),
+      2 => {
+        source_at = reader.relative(source_at)?;
+        target.extend(source.get(source_at..source_at + len).ok_or(format("BPS source copy past the end"))?);
+        source_at += len;
+      },
+      _ => {
+        target_at = reader.relative(target_at)?;
+        // Byte by byte, the copy may overlap what it writes
+        for _ in 0..len {
+          let db = *target.get(target_at).ok_or(format("BPS target copy past the end"))?;
+          target.push(db);
+          target_at += 1;
+        }
+      },
+    }
+  }
+  if target.len() != target_size {
+    return Err(format("BPS actions stop short of the target size"));
+  }
+  let crc32 = crc32fast::hash(&target);
+  if crc32 != footer(1) {
+    return Err(PatchError::TargetChecksum { crc32, expected: footer(1) });
+  }
+  Ok(target)
+}
+
+fn format(e: &str) -> PatchError {
+  PatchError::Format(e.to_string())
+}
+
+struct Reader<'a> {
+  patch: &'a [u8],
+  at: usize,
+}
+
+impl<'a> Reader<'a> {
+  fn rest(&self) -> &'a [u8] {
+    &self.patch[self.at.min(self.patch.len())..]
+  }
+
+  fn bytes(&mut self, len: usize) -> Result<&'a [u8], PatchError> {
+    let bytes = self.rest().get(..len).ok_or(format("patch ends early"))?;
+    self.at += len;
+    Ok(bytes)
+  }
+
+  fn byte(&mut self) -> Result<u8, PatchError> {
+    Ok(self.bytes(1)?[0])
+  }
+
+  /// Big-endian integer, as used by IPS
+  fn int(&mut self, len: usize) -> Result<usize, PatchError> {
+    Ok(self.bytes(len)?.iter().fold(0, |n, &db| n << 8 | db as usize))
+  }
+
+  /// BPS variable-length number
+  fn number(&mut self) -> Result<usize, PatchError> {
+    let mut n: usize = 0;
+    let mut shift: usize = 1;
+    loop {
+      let db = self.byte()?;
+      n = (db as usize & 0x7F).checked_mul(shift).and_then(|add| n.checked_add(add)).ok_or(format("BPS number too large"))?;
+      if db & 0x80 != 0 {
+        return Ok(n);
+      }
+      shift = shift.checked_mul(0x80).ok_or(format("BPS number too large"))?;
+      n = n.checked_add(shift).ok_or(format("BPS number too large"))?;
+    }
+  }
+
+  /// BPS copy offset, a sign bit and distance from `at`
+  fn relative(&mut self, at: usize) -> Result<usize, PatchError> {
+    let data = self.number()?;
+    let at = if data & 1 != 0 { at.checked_sub(data >> 1) } else { at.checked_add(data >> 1) };
+    at.ok_or(format("BPS copy before the start"))
+  }
+}
@@ -1,99 +1,308 @@
-#![allow(arithmetic_overflow)]
+use std::fmt;
 
-use crate::memory::{Memory, ROM_SIZE, MEM_SIZE, VIDEO_START};
+use crate::bus::{Bus, IoBus};
+use crate::disasm;
+use crate::loader::Binary;
+use crate::memory::Memory;
+use crate::symbols::Symbols;
 
 const HL: u8 = 0x02;
 
+/// Clock cycles (states) per opcode. Conditional CALL and RET are listed
+/// with their not-taken count, taking the branch adds `BRANCH_CYCLES`.
+/// Conditional jumps always fetch their operand and take 10 either way.
+pub const CYCLES: [u8; 256] = [
+  //0  1   2   3   4   5   6   7   8   9   A   B   C   D   E   F
+  4,  10, 7,  5,  5,  5,  7,  4,  4,  10, 7,  5,  5,  5,  7,  4,  // 0x00
+  4,  10, 7,  5,  5,  5,  7,  4,  4,  10, 7,  5,  5,  5,  7,  4,  // 0x10
+  4,  10, 16, 5,  5,  5,  7,  4,  4,  10, 16, 5,  5,  5,  7,  4,  // 0x20
+  4,  10, 13, 5,  10, 10, 10, 4,  4,  10, 13, 5,  5,  5,  7,  4,  // 0x30
+  5,  5,  5,  5,  5,  5,  7,  5,  5,  5,  5,  5,  5,  5,  7,  5,  // 0x40
+  5,  5,  5,  5,  5,  5,  7,  5,  5,  5,  5,  5,  5,  5,  7,  5,  // 0x50
+  5,  5,  5,  5,  5,  5,  7,  5,  5,  5,  5,  5,  5,  5,  7,  5,  // 0x60
+  7,  7,  7,  7,  7,  7,  7,  7,  5,  5,  5,  5,  5,  5,  7,  5,  // 0x70
+  4,  4,  4,  4,  4,  4,  7,  4,  4,  4,  4,  4,  4,  4,  7,  4,  // 0x80
+  4,  4,  4,  4,  4,  4,  7,  4,  4,  4,  4,  4,  4,  4,  7,  4,  // 0x90
+  4,  4,  4,  4,  4,  4,  7,  4,  4,  4,  4,  4,  4,  4,  7,  4,  // 0xA0
+  4,  4,  4,  4,  4,  4,  7,  4,  4,  4,  4,  4,  4,  4,  7,  4,  // 0xB0
+  5,  10, 10, 10, 11, 11, 7,  11, 5,  10, 10, 10, 11, 17, 7,  11, // 0xC0
+  5,  10, 10, 10, 11, 11, 7,  11, 5,  10, 10, 10, 11, 17, 7,  11, // 0xD0
+  5,  10, 10, 18, 11, 11, 7,  11, 5,  5,  10, 4,  11, 17, 7,  11, // 0xE0
+  5,  10, 10, 4,  11, 11, 7,  11, 5,  5,  10, 4,  11, 17, 7,  11, // 0xF0
+];
+
+/// Extra cycles for a conditional CALL or RET whose condition is met
+pub const BRANCH_CYCLES: u8 = 6;
+
+/// Cycles a halted processor idles per step
+pub const HALT_CYCLES: usize = 4;
+
 // SZ0A0P1C
-#[derive(Debug, Clone, Copy)]
-struct Flags {
-  s: bool,
-  z: bool,
-  ac: bool,
-  p: bool,
-  cy: bool,
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Flags {
+  pub s: bool,
+  pub z: bool,
+  pub ac: bool,
+  pub p: bool,
+  pub cy: bool,
 }
 
 impl Flags {
   fn new() -> Flags {
     Flags { s: false, z: false, ac: false, p: false, cy: false }
   }
+
+  /// Pack into the PSW flag byte, bits 5 and 3 always 0 and bit 1 always 1
+  pub fn to_psw(self) -> u8 {
+    (self.s as u8) << 7 |
+      (self.z as u8) << 6 |
+      (self.ac as u8) << 4 |
+      (self.p as u8) << 2 |
+      0x02 |
+      self.cy as u8
+  }
+
+  /// Unpack from the PSW flag byte, ignoring the fixed bits
+  pub fn from_psw(db: u8) -> Flags {
+    Flags {
+      s: (db & 0x80) != 0,
+      z: (db & 0x40) != 0,
+      ac: (db & 0x10) != 0,
+      p: (db & 0x04) != 0,
+      cy: (db & 0x01) != 0,
+    }
+  }
 }
 
-pub struct Processor {
-  pub ic: u128,
+impl fmt::Display for Flags {
+  /// Set flags by letter, clear ones as '-', e.g. `SZ-P-`
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let flags = [(self.s, 'S'), (self.z, 'Z'), (self.ac, 'A'), (self.p, 'P'), (self.cy, 'C')];
+    for (set, name) in flags {
+      write!(f, "{}", if set { name } else { '-' })?;
+    }
+    Ok(())
+  }
+}
+
+/// Snapshot of the programmer-visible processor state
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CpuState {
   pub a: u8,
+  pub b: u8,
+  pub c: u8,
+  pub d: u8,
+  pub e: u8,
+  pub h: u8,
+  pub l: u8,
+  pub sp: u16,
+  pub pc: u16,
+  pub flags: Flags,
+  pub ie: bool,
+  pub halted: bool,
+  pub cycles: u64,
+}
+
+impl CpuState {
+  pub fn bc(&self) -> u16 {
+    (self.b as u16) << 8 | self.c as u16
+  }
+
+  pub fn de(&self) -> u16 {
+    (self.d as u16) << 8 | self.e as u16
+  }
+
+  pub fn hl(&self) -> u16 {
+    (self.h as u16) << 8 | self.l as u16
+  }
+
+  /// A and the flags as pushed by PUSH PSW
+  pub fn psw(&self) -> u16 {
+    (self.a as u16) << 8 | self.flags.to_psw() as u16
+  }
+
+  pub fn set_bc(&mut self, hblb: u16) {
+    self.b = (hblb >> 8) as u8;
+    self.c = hblb as u8;
+  }
+
+  pub fn set_de(&mut self, hblb: u16) {
+    self.d = (hblb >> 8) as u8;
+    self.e = hblb as u8;
+  }
+
+  pub fn set_hl(&mut self, hblb: u16) {
+    self.h = (hblb >> 8) as u8;
+    self.l = hblb as u8;
+  }
+
+  pub fn set_psw(&mut self, hblb: u16) {
+    self.a = (hblb >> 8) as u8;
+    self.flags = Flags::from_psw(hblb as u8);
+  }
+}
+
+impl fmt::Display for CpuState {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(
+      f,
+      "PC={:04X} SP={:04X} A={:02X} BC={:04X} DE={:04X} HL={:04X} F={} IE={} HLT={} CYC={}",
+      self.pc, self.sp, self.a, self.bc(), self.de(), self.hl(), self.flags,
+      self.ie as u8, self.halted as u8, self.cycles,
+    )
+  }
+}
+
+/// What a halted processor does when interrupts are disabled, so that no
+/// interrupt can ever wake it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HaltPolicy {
+  /// Keep idling like the hardware does
+  Wait,
+  /// Stop consuming cycles, for test programs that end with DI; HLT
+  Stop,
+}
+
+pub struct Processor<B: Bus = Memory> {
+  pub ic: u128,
+  cycles: u64,
+  a: u8,
   b: u8,
   c: u8,
   d: u8,
   e: u8,
   h: u8,
   l: u8,
-  sp: usize,
-  pub pc: usize,
+  sp: u16,
+  pc: u16,
   cc: Flags,
-  pub ie: bool,
-  pub mem: Memory,
+  ie: bool,
+  halted: bool,
+  pub halt_policy: HaltPolicy,
+  ei_delay: bool,
+  irq: Option<[u8; 3]>,
+  inta: Option<([u8; 3], usize)>,
+  pub mem: B,
 }
 
 impl Processor {
-  pub fn new(rom: [u8; ROM_SIZE]) -> Processor {
-    Processor { ic: 0, a: 0, b: 0, c: 0, d: 0, e: 0, h: 0, l: 0, sp: 0, pc: 0, cc: Flags::new(), ie: false, mem: Memory::new(rom) }
+  /// Processor on 64 KiB of RAM, for test programs and CP/M software
+  pub fn flat() -> Processor {
+    Processor::new(Memory::flat())
   }
 
-  /// execute single command
-  pub fn exec(&mut self) -> usize {
+  /// Place a program in memory, ROM included, and start at its entry point
+  /// if it has one
+  pub fn load(&mut self, binary: &Binary) {
+    for segment in &binary.segments {
+      self.mem.load(segment.address, &segment.bytes);
+    }
+    if let Some(entry) = binary.entry {
+      self.pc = entry;
+    }
+  }
+}
+
+impl<B: Bus> Processor<B> {
+  pub fn new(mem: B) -> Processor<B> {
+    Processor {
+      ic: 0, cycles: 0, a: 0, b: 0, c: 0, d: 0, e: 0, h: 0, l: 0, sp: 0, pc: 0, cc: Flags::new(),
+      ie: false, halted: false, halt_policy: HaltPolicy::Wait, ei_delay: false, irq: None, inta: None, mem,
+    }
+  }
+
+  /// execute single command, or the pending interrupt instruction if one
+  /// can be accepted at this instruction boundary. A halted processor only
+  /// idles until an interrupt arrives.
+  pub fn exec(&mut self, io: &mut dyn IoBus) -> usize {
+    if self.is_stopped() {
+      return 0;
+    }
+    let ei_delay = self.ei_delay;
+    self.ei_delay = false;
+    let cycles = match self.irq {
+      Some(instruction) if self.ie && !ei_delay => {
+        self.irq = None;
+        self.ie = false;
+        self.halted = false;
+        self.inta = Some((instruction, 0));
+        let cycles = self.decode(io);
+        self.inta = None;
+        cycles
+      },
+      _ if self.halted => HALT_CYCLES,
+      _ => self.decode(io),
+    };
+    self.cycles += cycles as u64;
+    self.mem.tick(cycles);
+    cycles
+  }
+
+  /// Fetch, decode and execute the instruction at PC
+  fn decode(&mut self, io: &mut dyn IoBus) -> usize {
     self.ic += 1;
-    let opcode = self.mem.read(self.pc);
+    let opcode = self.next_byte();
     let d = (opcode >> 3) & 0b111;
     let s = opcode & 0b111;
     let rp = (opcode >> 4) & 0b11;
-    let hblb = if self.pc + 2 < MEM_SIZE {
-      (self.mem.read(self.pc+2) as u16) << 8 | self.mem.read(self.pc+1) as u16
-    } else {
-      0
-    };
-    
+    let mut cycles = CYCLES[opcode as usize];
+
     match opcode {
+      0x00 | 0x08 | 0x10 | 0x18 | 0x20 | 0x28 | 0x30 | 0x38 => (),
       0x01 | 0x11 | 0x21 | 0x31 => {
+        let hblb = self.next_word();
         self.set_reg_pair(rp, hblb, false);
-        self.pc += 2;
       },
       0x02 | 0x12 => {
-        let db = self.get_reg_pair(rp, false) as usize;
-        self.mem.write(db, self.a);
+        let hblb = self.get_reg_pair(rp, false);
+        self.write(hblb, self.a);
       },
-      0x03 | 0x13 | 0x23 | 0x33 => self.set_reg_pair(rp, self.get_reg_pair(rp, false) + 1, false),
+      0x03 | 0x13 | 0x23 | 0x33 => self.set_reg_pair(rp, self.get_reg_pair(rp, false).wrapping_add(1), false),
       0x04 | 0x0C | 0x14 | 0x1C | 0x24 | 0x2C | 0x34 | 0x3C => {
-        self.set_reg(d, self.get_reg(d) + 1);
-        self.cc(self.get_reg(d));
+        let ans = self.get_reg(d).wrapping_add(1);
+        self.set_reg(d, ans);
+        self.cc(ans);
+        self.cc.ac = (ans & 0x0F) == 0x00;
       },
       0x05 | 0x0D | 0x15 | 0x1D | 0x25 | 0x2D | 0x35 | 0x3D => {
-        self.set_reg(d, self.get_reg(d) + (!1 + 1));
-        self.cc(self.get_reg(d));
+        let ans = self.get_reg(d).wrapping_sub(1);
+        self.set_reg(d, ans);
+        self.cc(ans);
+        self.cc.ac = (ans & 0x0F) != 0x0F;
       },
       0x06 | 0x0E | 0x16 | 0x1E | 0x26 | 0x2E | 0x36 | 0x3E => {
-        self.set_reg(d, self.mem.read(self.pc+1));
-        self.pc += 1;
-      }
+        let db = self.next_byte();
+        self.set_reg(d, db);
+      },
       0x07 | 0x17 => self.rl(opcode == 0x17),
       0x09 | 0x19 | 0x29 | 0x39 => self.dad(rp),
-      0x0A | 0x1A => self.a = self.mem.read(self.get_reg_pair(rp, false) as usize),
-      0x0B | 0x1B | 0x2B | 0x3B => self.set_reg_pair(rp, self.get_reg_pair(rp, false) - 1, false),
+      0x0A | 0x1A => self.a = self.read(self.get_reg_pair(rp, false)),
+      0x0B | 0x1B | 0x2B | 0x3B => self.set_reg_pair(rp, self.get_reg_pair(rp, false).wrapping_sub(1), false),
       0x0F | 0x1F => self.rr(opcode == 0x1F),
+      0x22 => {
+        let hblb = self.next_word();
+        self.write(hblb, self.l);
+        self.write(hblb.wrapping_add(1), self.h);
+      },
+      0x27 => self.daa(),
+      0x2A => {
+        let hblb = self.next_word();
+        self.l = self.read(hblb);
+        self.h = self.read(hblb.wrapping_add(1));
+      },
       0x2F => self.a = !self.a,
       0x32 => {
-        self.mem.write(hblb as usize, self.a);
-        self.pc += 2;
+        let hblb = self.next_word();
+        self.write(hblb, self.a);
       },
       0x37 => self.cc.cy = true,
       0x3A => {
-        self.a = self.mem.read(hblb as usize);
-        self.pc += 2;
+        let hblb = self.next_word();
+        self.a = self.read(hblb);
       },
       0x3F => self.cc.cy = !self.cc.cy,
-      0x41..=0x75 | 0x77..=0x7F => self.set_reg(d, self.get_reg(s)),
-      0x76 => return 2000,
+      0x40..=0x75 | 0x77..=0x7F => self.set_reg(d, self.get_reg(s)),
+      0x76 => self.halted = true,
       0x80..=0x8F => self.add(self.get_reg(s), opcode >= 0x88),
       0x90..=0x9F => self.sub(self.get_reg(s), opcode >= 0x98),
       0xA0..=0xA7 => self.and(self.get_reg(s)),
@@ -102,89 +311,118 @@ impl Processor {
       0xB8..=0xBF => self.cmp(self.get_reg(s)),
       0xC0 | 0xC8 | 0xD0 | 0xD8 | 0xE0 | 0xE8 | 0xF0 | 0xF8 => {
         if self.get_ccc(d) {
-          self.pc = (self.pop() as usize) - 1;
+          self.pc = self.pop();
+          cycles += BRANCH_CYCLES;
         }
       },
-      0xC1 | 0xD1 | 0xE1 => {
+      0xC1 | 0xD1 | 0xE1 | 0xF1 => {
         let db = self.pop();
         self.set_reg_pair(rp, db, true);
       },
-      0xF1 => {
-        let db = self.pop() - 1;
-        self.set_reg_pair(rp, db, true);
-      },
       0xC2 | 0xCA | 0xD2 | 0xDA | 0xE2 | 0xEA | 0xF2 | 0xFA => {
+        let hblb = self.next_word();
         if self.get_ccc(d) {
-          self.pc = (hblb as usize) - 1;
-        } else {
-          self.pc += 2;
+          self.pc = hblb;
         }
       },
-      0xC3 => self.pc = (hblb as usize) - 1,
+      0xC3 | 0xCB => self.pc = self.next_word(),
       0xC4 | 0xCC | 0xD4 | 0xDC | 0xE4 | 0xEC | 0xF4 | 0xFC => {
+        let hblb = self.next_word();
         if self.get_ccc(d) {
-          self.push((self.pc + 3) as u16);
-          self.pc = (hblb as usize) - 1;
-        } else {
-          self.pc += 2;
+          self.call(hblb);
+          cycles += BRANCH_CYCLES;
         }
       },
       0xC5 | 0xD5 | 0xE5 | 0xF5 => self.push(self.get_reg_pair(rp, true)),
       0xC6 | 0xCE => {
-        self.add(self.mem.read(self.pc+1), opcode == 0xCE);
-        self.pc += 1;
+        let db = self.next_byte();
+        self.add(db, opcode == 0xCE);
       },
-      0xC7 | 0xCF | 0xD7 | 0xDF | 0xE7 | 0xEF | 0xF7 | 0xFF => {
-        self.push((self.pc + 3) as u16);
-        self.pc = (d as usize) - 1;
+      0xC7 | 0xCF | 0xD7 | 0xDF | 0xE7 | 0xEF | 0xF7 | 0xFF => self.call((d as u16) << 3),
+      0xC9 | 0xD9 => self.pc = self.pop(),
+      0xCD | 0xDD | 0xED | 0xFD => {
+        let hblb = self.next_word();
+        self.call(hblb);
       },
-      0xC9 => self.pc = (self.pop() as usize) - 1,
-      0xCD => {
-        self.push((self.pc + 3) as u16);
-        self.pc = (hblb as usize) - 1;
+      0xD3 => {
+        let port = self.next_byte();
+        io.output(port, self.a);
       },
-      0xD3 | 0xDB => self.pc += 1,
       0xD6 | 0xDE => {
-        self.sub(self.mem.read(self.pc+1), opcode == 0xDE);
-        self.pc += 1;
+        let db = self.next_byte();
+        self.sub(db, opcode == 0xDE);
+      },
+      0xDB => {
+        let port = self.next_byte();
+        self.a = io.input(port);
       },
       0xE3 => {
-        let (hb, lb) = (self.mem.read(self.sp+1), self.mem.read(self.sp));
-        self.mem.write(self.sp+1, self.h);
-        self.mem.write(self.sp, self.l);
+        let (hb, lb) = (self.read(self.sp.wrapping_add(1)), self.read(self.sp));
+        self.write(self.sp.wrapping_add(1), self.h);
+        self.write(self.sp, self.l);
         self.h = hb;
         self.l = lb;
       },
       0xE6 => {
-        self.and(self.mem.read(self.pc+1));
-        self.pc += 1;
+        let db = self.next_byte();
+        self.and(db);
       },
-      0xE9 => self.pc = (self.get_reg_pair(HL, false) as usize) - 1,
+      0xE9 => self.pc = self.get_reg_pair(HL, false),
       0xEB => {
         let (hb, lb) = (self.d, self.e);
         self.d = self.h;
         self.e = self.l;
         self.h = hb;
         self.l = lb;
-      }
+      },
       0xEE => {
-        self.xor(self.mem.read(self.pc+1));
-        self.pc += 1;
+        let db = self.next_byte();
+        self.xor(db);
+      },
+      0xF3 => self.ie = false,
+      0xFB => {
+        self.ie = true;
+        self.ei_delay = true;
       },
-      0xF3 | 0xFB => self.ie = opcode == 0xFB,
       0xF6 => {
-        self.or(self.mem.read(self.pc+1));
-        self.pc += 1;
+        let db = self.next_byte();
+        self.or(db);
       },
-      0xF9 => self.sp = self.get_reg_pair(0x02, false) as usize,
+      0xF9 => self.sp = self.get_reg_pair(HL, false),
       0xFE => {
-        self.cmp(self.mem.read(self.pc+1));
-        self.pc += 1;
+        let db = self.next_byte();
+        self.cmp(db);
       },
-      _ => (),
     }
-    self.pc += 1;
-    self.get_duration(opcode)
+    cycles as usize
+  }
+
+  fn read(&self, hblb: u16) -> u8 {
+    self.mem.read(hblb)
+  }
+
+  fn write(&mut self, hblb: u16, db: u8) {
+    self.mem.write(hblb, db);
+  }
+
+  /// Fetch the byte at PC and advance past it
+  fn next_byte(&mut self) -> u8 {
+    // During interrupt acknowledge the instruction comes from the data bus
+    if let Some((instruction, i)) = &mut self.inta {
+      let db = instruction[*i];
+      *i = (*i + 1).min(2);
+      return db;
+    }
+    let db = self.read(self.pc);
+    self.pc = self.pc.wrapping_add(1);
+    db
+  }
+
+  /// Fetch a little-endian 16-bit operand at PC and advance past it
+  fn next_word(&mut self) -> u16 {
+    let lb = self.next_byte() as u16;
+    let hb = self.next_byte() as u16;
+    hb << 8 | lb
   }
 
   fn get_reg(&self, s: u8) -> u8 {
@@ -195,7 +433,7 @@ impl Processor {
       0x3 => self.e,
       0x4 => self.h,
       0x5 => self.l,
-      0x6 => self.mem.read(((self.h as usize) << 8) | self.l as usize),
+      0x6 => self.read(self.get_reg_pair(HL, false)),
       _ => self.a,
     }
   }
@@ -208,7 +446,7 @@ impl Processor {
       0x3 => self.e = db,
       0x4 => self.h = db,
       0x5 => self.l = db,
-      0x6 => self.mem.write(((self.h as usize) << 8) | self.l as usize, db),
+      0x6 => self.write(self.get_reg_pair(HL, false), db),
       _ => self.a = db,
     }
   }
@@ -219,14 +457,9 @@ impl Processor {
       0x1 => (self.d as u16) << 8 | self.e as u16,
       0x2 => (self.h as u16) << 8 | self.l as u16,
       _ => if psw {
-        (self.a as u16) << 8 |
-          (self.cc.ac as u16) << 4 |
-          (self.cc.cy as u16) << 3 |
-          (self.cc.p as u16) << 2 |
-          (self.cc.s as u16) << 1 |
-          self.cc.z as u16
+        (self.a as u16) << 8 | self.cc.to_psw() as u16
       } else {
-        self.sp as u16
+        self.sp
       },
     }
   }
@@ -247,13 +480,9 @@ impl Processor {
       },
       _ => if psw {
         self.a = (db >> 8) as u8;
-        self.cc.ac = (db & 0x10) != 0;
-        self.cc.cy = (db & 0x08) != 0;
-        self.cc.p = (db & 0x04) != 0;
-        self.cc.s = (db & 0x02) != 0;
-        self.cc.z = (db & 0x01) != 0;
-      } else {  
-        self.sp = db as usize
+        self.cc = Flags::from_psw(db as u8);
+      } else {
+        self.sp = db
       },
     }
   }
@@ -271,90 +500,9 @@ impl Processor {
     }
   }
 
-  fn get_opcode(&self, index: usize) -> &'static str {
-    match self.mem.read(index) {
-      0x01	=> "LXI B,D16",
-      0x05	=> "DCR B",
-      0x06	=> "MVI B,D8",
-      0x09	=> "DAD B",
-      0x0d	=> "DCR C",
-      0x0e	=> "MVI C,D8",
-      0x0f	=> "RRC",
-      0x11	=> "LXI D,D16",
-      0x13	=> "INX D",
-      0x19	=> "DAD D",
-      0x1a	=> "LDAX D",
-      0x21	=> "LXI H,D16",
-      0x23	=> "INX H",
-      0x26	=> "MVI H,D8",
-      0x29	=> "DAD H",
-      0x31	=> "LXI SP,D16",
-      0x32	=> "STA adr",
-      0x36	=> "MVI M,D8",
-      0x3a	=> "LDA adr",
-      0x3e	=> "MVI A,D8",
-      0x56	=> "MOV D,M",
-      0x5e	=> "MOV E,M",
-      0x66	=> "MOV H,M",
-      0x6f	=> "MOV L,A",
-      0x77	=> "MOV M,A",
-      0x7a	=> "MOV A,D",
-      0x7b	=> "MOV A,E",
-      0x7c	=> "MOV A,H",
-      0x7e	=> "MOV A,M",
-      0xa7	=> "ANA A",
-      0xaf	=> "XRA A",
-      0xc1	=> "POP B",
-      0xc2	=> "JNZ adr",
-      0xc3	=> "JMP adr",
-      0xc5	=> "PUSH B",
-      0xc6	=> "ADI D8",
-      0xc9	=> "RET",
-      0xcd	=> "CALL adr",
-      0xd1	=> "POP D",
-      0xd3	=> "OUT D8",
-      0xd5	=> "PUSH D",
-      0xe1	=> "POP H",
-      0xe5	=> "PUSH H",
-      0xe6	=> "ANI D8",
-      0xeb	=> "XCHG",
-      0xf1	=> "POP PSW",
-      0xf5	=> "PUSH PSW",
-      0xfb	=> "EI",
-      0xfe	=> "CPI D8",
-      _ => "NOP",
-    }
-  }
-
-  fn get_duration(&self, opcode: u8) -> usize {
-    match opcode {
-      0x00 | 0x07 | 0x08 | 0x17 | 0x18 | 0x27 | 0x28 | 0x37 | 0x38 |
-      0x0F | 0x1F | 0x2F | 0x3F |
-      0x80..=0x85 | 0x87..=0x8D | 0x8F |
-      0x90..=0x95 | 0x97..=0x9D | 0x9F |
-      0xA0..=0xA5 | 0xA7..=0xAD | 0xAF |
-      0xB0..=0xB5 | 0xB7..=0xBD | 0xBF |
-      0xF3 | 0xFB => 4,
-      0x02 | 0x12 | 0x0A | 0x2A |
-      0x06 | 0x16 | 0x26 | 0x46 | 0x56 | 0x66 | 0x86 | 0x96 | 0xA6 | 0xB6 | 0xC6 | 0xD6 | 0xE6 | 0xF6 |
-      0x0E | 0x1E | 0x2E | 0x3E | 0x4E | 0x5E | 0x6E | 0x7E | 0x8E | 0x9E | 0xAE | 0xBE | 0xCE | 0xDE | 0xEE | 0xFE |
-      0x70..=0x77 => 7,
-      0x01 | 0x11 | 0x21 | 0x31 | 0x34..=0x36 |
-      0x09 | 0x19 | 0x29 | 0x39 |
-      0xC1..=0xC3 | 0xD1..=0xD3 | 0xE1 | 0xE2 | 0xF1 | 0xF2 |
-      0xC9..=0xCB | 0xD9..=0xDB | 0xEA | 0xFA => 10,
-      0xC4 | 0xC5 | 0xD4 | 0xD5 | 0xE4 | 0xE5 | 0xF4 | 0xF5 |
-      0xC7 | 0xD7 | 0xE7 | 0xF7 |
-      0xCC | 0xDC | 0xEC | 0xFC |
-      0xCF | 0xDF | 0xEF | 0xFF => 11,
-      0x32 | 0x3A => 13,
-      0xCD => 17,
-      _ => 5,
-    }
-  }
-
   /// And value with A
   fn and(&mut self, db: u8) {
+    self.cc.ac = ((self.a | db) & 0x08) != 0;
     self.a &= db;
     self.cc.cy = false;
     self.cc(self.a);
@@ -362,27 +510,32 @@ impl Processor {
 
   /// Add value to A
   fn add(&mut self, db: u8, c: bool) {
-    let mut ans = self.a as u16 + db as u16;
-    if c {
-      ans += self.cc.cy as u16;
-    }
+    let cy = (c && self.cc.cy) as u16;
+    let ans = self.a as u16 + db as u16 + cy;
+    self.cc.ac = (self.a & 0x0F) as u16 + (db & 0x0F) as u16 + cy > 0x0F;
     self.cc.cy = ans > 0xFF;
     self.a = ans as u8;
     self.cc(self.a);
   }
 
+  /// Push PC and jump to subroutine
+  fn call(&mut self, hblb: u16) {
+    self.push(self.pc);
+    self.pc = hblb;
+  }
+
   /// Change control flags
   fn cc(&mut self, ans: u8) {
     self.cc.s = (ans & 0x80) != 0;
     self.cc.z = ans == 0;
-    self.cc.p = ans % 2 == 0;
+    self.cc.p = ans.count_ones().is_multiple_of(2);
   }
 
   /// Compare value with A
   fn cmp(&mut self, db: u8) {
-    let ans = self.a + (!db + 1);
-    self.cc(ans);
-    self.cc.cy = self.a < db;
+    let a = self.a;
+    self.sub(db, false);
+    self.a = a;
   }
 
   /// Double add
@@ -392,61 +545,128 @@ impl Processor {
     self.set_reg_pair(HL, ans as u16, false);
   }
 
-  pub fn int(&mut self, int_num: usize) {
-    if self.ie {
-      self.push(self.pc as u16);
-      self.pc = 8 * int_num;
+  /// Decimal adjust A after BCD addition
+  fn daa(&mut self) {
+    let mut correction = 0;
+    let mut cy = self.cc.cy;
+    if self.cc.ac || (self.a & 0x0F) > 9 {
+      correction |= 0x06;
+    }
+    if self.cc.cy || (self.a >> 4) > 9 || ((self.a >> 4) >= 9 && (self.a & 0x0F) > 9) {
+      correction |= 0x60;
+      cy = true;
     }
+    self.add(correction, false);
+    self.cc.cy = cy;
+  }
+
+  /// Copy of the current registers and flags
+  pub fn state(&self) -> CpuState {
+    CpuState {
+      a: self.a, b: self.b, c: self.c, d: self.d, e: self.e, h: self.h, l: self.l,
+      sp: self.sp,
+      pc: self.pc,
+      flags: self.cc,
+      ie: self.ie,
+      halted: self.halted,
+      cycles: self.cycles,
+    }
+  }
+
+  /// Restore registers and flags from a snapshot
+  pub fn set_state(&mut self, state: &CpuState) {
+    self.a = state.a;
+    self.b = state.b;
+    self.c = state.c;
+    self.d = state.d;
+    self.e = state.e;
+    self.h = state.h;
+    self.l = state.l;
+    self.sp = state.sp;
+    self.pc = state.pc;
+    self.cc = state.flags;
+    self.ie = state.ie;
+    self.halted = state.halted;
+    self.cycles = state.cycles;
+  }
+
+  /// Halted with interrupts disabled under `HaltPolicy::Stop`
+  pub fn is_stopped(&self) -> bool {
+    self.halted && !self.ie && self.halt_policy == HaltPolicy::Stop
+  }
+
+  /// RESET line: start again from 0 with interrupts disabled. Registers
+  /// and memory keep their contents, as on the chip.
+  pub fn reset(&mut self) {
+    self.pc = 0;
+    self.ie = false;
+    self.halted = false;
+    self.ei_delay = false;
+    self.irq = None;
+    self.inta = None;
+  }
+
+  /// Request an interrupt, supplying the instruction the interrupting device
+  /// places on the data bus (up to three bytes, usually a single RST).
+  /// It stays pending until interrupts are enabled, replacing any earlier
+  /// request, and is taken at the next instruction boundary.
+  pub fn interrupt(&mut self, instruction: &[u8]) {
+    let mut db = [0x00; 3];
+    let len = instruction.len().min(3);
+    db[..len].copy_from_slice(&instruction[..len]);
+    self.irq = Some(db);
+  }
+
+  /// Request an interrupt with RST `n` on the data bus
+  pub fn rst(&mut self, n: u8) {
+    self.interrupt(&[0xC7 | (n & 0b111) << 3]);
   }
 
   /// Or value with A
   fn or(&mut self, db: u8) {
     self.a |= db;
     self.cc.cy = false;
+    self.cc.ac = false;
     self.cc(self.a);
   }
 
   fn pop(&mut self) -> u16 {
-    let ret = (self.mem.read(self.sp + 1) as u16) << 8 | self.mem.read(self.sp) as u16;
-    self.sp += 2;
+    let ret = (self.read(self.sp.wrapping_add(1)) as u16) << 8 | self.read(self.sp) as u16;
+    self.sp = self.sp.wrapping_add(2);
     ret
   }
 
-  pub fn print(&self) {
+  pub fn print(&self, symbols: &Symbols) {
     let print_state = [
       format!("A:\t{:02X}\t\tS:\t{}\tMEM:\t", self.a, self.cc.s as u8),
       format!("BC:\t{:02X} {:02X}\t\tZ:\t{}\t\t", self.b, self.c, self.cc.z as u8),
       format!("DE:\t{:02X} {:02X}\t\tAC:\t{}\t\t", self.d, self.e, self.cc.ac as u8),
       format!("HL:\t{:02X} {:02X}\t\tP:\t{}\t\t", self.h, self.l, self.cc.p as u8),
       format!("SP:\t{:04X}\t\tCY:\t{}\t\t", self.sp, self.cc.cy as u8),
-      format!("\t{:02X} {:02X}\t\tIC:\t{}\t\t", self.mem.read(self.sp + 1), self.mem.read(self.sp), self.ic),
+      format!("\t{:02X} {:02X}\t\tIC:\t{}\t\t", self.read(self.sp.wrapping_add(1)), self.read(self.sp), self.ic),
+      format!("IE:\t{}\t\tHLT:\t{}\t\t", self.ie as u8, self.halted as u8),
     ];
 
-    for i in 0..print_state.len() {
+    // Memory around PC and HL, wrapping at the ends of the address space
+    let hl = self.get_reg_pair(HL, false);
+    for (i, state) in print_state.iter().enumerate() {
       let anno = if i == 0 { "PC:\t" } else if i == 2 { "->\t" } else { "\t" };
-      let opcode = if (self.pc + i >= 2) && (self.pc + i + print_state.len() < MEM_SIZE - 2) {
-        let index = self.pc + i - 2;
-        let opcode = self.mem.read(index);
-        let hblb = ((self.h as usize) << 8) | (self.l as usize);
-        let mem_index = if (hblb + i >= 2) && (hblb + i + print_state.len() < MEM_SIZE - 2) { 
-          hblb + i - 2
-        } else {
-          0 
-        };
-        let memcode = self.mem.read(mem_index);
-        format!("{:04X} | {:02X}\t{}{:04X} | {:02X} {}", mem_index, memcode, anno, index, opcode, self.get_opcode(index))
-      } else {
-        String::new()
-      };
-      println!("{}{}", print_state[i], opcode);
+      let index = self.pc.wrapping_add(i as u16).wrapping_sub(2);
+      let opcode = self.read(index);
+      let mem_index = hl.wrapping_add(i as u16).wrapping_sub(2);
+      let memcode = self.read(mem_index);
+      let name = symbols.name(index).map(|name| format!("\t; {}", name)).unwrap_or_default();
+      let instruction = symbols.instruction(&disasm::decode(&self.mem, index));
+      let opcode = format!("{:04X} | {:02X}\t{}{:04X} | {:02X} {}{}", mem_index, memcode, anno, index, opcode, instruction, name);
+      println!("{}{}", state, opcode);
     }
   }
 
   /// Push 16-bit data onto stack
   fn push(&mut self, hblb: u16) {
-    self.mem.write(self.sp-1, (hblb >> 8) as u8);
-    self.mem.write(self.sp-2, hblb as u8);
-    self.sp -= 2;
+    self.write(self.sp.wrapping_sub(1), (hblb >> 8) as u8);
+    self.write(self.sp.wrapping_sub(2), hblb as u8);
+    self.sp = self.sp.wrapping_sub(2);
   }
 
   fn rl(&mut self, c: bool) {
@@ -454,7 +674,7 @@ impl Processor {
     self.a = if c {
       self.a << 1 | self.cc.cy as u8
     } else {
-      self.a << 1 | self.a >> 7
+      self.a.rotate_left(1)
     };
     self.cc.cy = hb;
   }
@@ -464,26 +684,29 @@ impl Processor {
     self.a = if c {
       (self.cc.cy as u8) << 7 | self.a >> 1
     } else {
-      self.a << 7 | self.a >> 1
+      self.a.rotate_right(1)
     };
     self.cc.cy = lb;
   }
 
   /// Subtract value from A
-  fn sub(&mut self, mut db: u8, c: bool) {
-    if c {
-      db += self.cc.cy as u8;
-    }
-    let ans = self.a + (!db + 1);
-    self.cc(ans);
-    self.cc.cy = self.a < db;
-    self.a = ans;
+  ///
+  /// The ALU adds the complement of the operand, so AC is the carry out of
+  /// bit 3 of that sum while CY is the inverted carry (the borrow).
+  fn sub(&mut self, db: u8, c: bool) {
+    let cy = (c && self.cc.cy) as u16;
+    let ans = (self.a as u16).wrapping_sub(db as u16).wrapping_sub(cy);
+    self.cc.ac = (self.a & 0x0F) as u16 + (!db & 0x0F) as u16 + (1 - cy) > 0x0F;
+    self.cc.cy = ans > 0xFF;
+    self.a = ans as u8;
+    self.cc(self.a);
   }
 
   /// Xor value with A
   fn xor(&mut self, db: u8) {
     self.a ^= db;
     self.cc.cy = false;
+    self.cc.ac = false;
     self.cc(self.a);
   }
 }
\ No newline at end of file
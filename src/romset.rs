@@ -0,0 +1,197 @@
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use sha1::{Digest, Sha1};
+
+use crate::memory::ROM_SIZE;
+
+/// One ROM chip of a set
+pub struct Chip {
+  pub name: &'static str,
+  pub offset: usize,
+  pub size: usize,
+  pub crc32: u32,
+  /// Lower case hex
+  pub sha1: &'static str,
+}
+
+/// Known ROM set, named as in MAME
+pub struct RomSet {
+  pub name: &'static str,
+  pub description: &'static str,
+  pub chips: &'static [Chip],
+}
+
+pub const SETS: &[RomSet] = &[
+  RomSet {
+    name: "invaders",
+    description: "Space Invaders (Midway)",
+    chips: &[
+      Chip { name: "invaders.h", offset: 0x0000, size: 0x0800, crc32: 0x734f5ad8, sha1: "ff6200af4c9110d8181249cbcef1a8a40fa40b7f" },
+      Chip { name: "invaders.g", offset: 0x0800, size: 0x0800, crc32: 0x6bfaca4a, sha1: "16f48649b531bdef8c2d1446c429b5f414524350" },
+      Chip { name: "invaders.f", offset: 0x1000, size: 0x0800, crc32: 0x0ccead96, sha1: "537aef03468f63c5b9e11dd61e253f7ae17d9743" },
+      Chip { name: "invaders.e", offset: 0x1800, size: 0x0800, crc32: 0x14e538b0, sha1: "1d6ca0c99f9df71e2990b610deb9d7da0125e2d8" },
+    ],
+  },
+];
+
+#[derive(Debug)]
+pub enum RomError {
+  Io(PathBuf, io::Error),
+  Zip(PathBuf, String),
+  /// Chips found neither by name nor by checksum
+  Missing(&'static str, Vec<&'static str>),
+  Size { name: String, size: usize, expected: usize },
+  BadDump { name: String, crc32: u32, expected: u32 },
+  /// CRC32 matches but SHA1 does not
+  BadSha1 { name: String, sha1: String, expected: &'static str },
+}
+
+impl fmt::Display for RomError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      RomError::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+      RomError::Zip(path, e) => write!(f, "{}: {}", path.display(), e),
+      RomError::Missing(set, chips) => write!(f, "{}: missing {}", set, chips.join(", ")),
+      RomError::Size { name, size, expected } => write!(f, "{}: {} bytes, expected {}", name, size, expected),
+      RomError::BadDump { name, crc32, expected } => write!(f, "{}: bad dump, CRC32 {:08x}, expected {:08x}", name, crc32, expected),
+      RomError::BadSha1 { name, sha1, expected } => write!(f, "{}: bad dump, SHA1 {}, expected {}", name, sha1, expected),
+    }
+  }
+}
+
+impl std::error::Error for RomError {}
+
+/// ROM image assembled from a set
+pub struct Rom {
+  pub image: [u8; ROM_SIZE],
+  pub set: &'static RomSet,
+  /// Problems that did not stop loading, like misnamed chips
+  pub warnings: Vec<String>,
+}
+
+/// File found while looking for chips
+struct Candidate {
+  name: String,
+  bytes: Vec<u8>,
+}
+
+impl Candidate {
+  fn crc32(&self) -> u32 {
+    crc32fast::hash(&self.bytes)
+  }
+}
+
+/// Load the Space Invaders ROM set from a combined image, a directory, a
+/// zip file or the chip files themselves. With `verify` every chip must
+/// match the checksums of the set, otherwise only the sizes are checked.
+pub fn load(paths: &[PathBuf], verify: bool) -> Result<Rom, RomError> {
+  let set = &SETS[0];
+  let total: usize = set.chips.iter().map(|chip| chip.size).sum();
+  let mut candidates = Vec::new();
+  for path in paths {
+    let read = |path: &Path| fs::read(path).map_err(|e| RomError::Io(path.to_path_buf(), e));
+    if path.is_dir() {
+      let entries = fs::read_dir(path).map_err(|e| RomError::Io(path.clone(), e))?;
+      for entry in entries {
+        let entry = entry.map_err(|e| RomError::Io(path.clone(), e))?;
+        if entry.path().is_file() {
+          candidates.push(Candidate { name: file_name(&entry.path()), bytes: read(&entry.path())? });
+        }
+      }
+    } else if is_zip(path) {
+      candidates.extend(read_zip(path)?);
+    } else {
+      let bytes = read(path)?;
+      if paths.len() == 1 && bytes.len() == total {
+        // Combined image, split back into chips
+        for chip in set.chips {
+          candidates.push(Candidate { name: chip.name.to_string(), bytes: bytes[chip.offset..chip.offset + chip.size].to_vec() });
+        }
+      } else if paths.len() == 1 && !set.chips.iter().any(|chip| chip.name.eq_ignore_ascii_case(&file_name(path))) {
+        return Err(RomError::Size { name: path.display().to_string(), size: bytes.len(), expected: total });
+      } else {
+        candidates.push(Candidate { name: file_name(path), bytes });
+      }
+    }
+  }
+  assemble(set, &candidates, verify)
+}
+
+/// Pick a file for every chip and place it in the image
+fn assemble(set: &'static RomSet, candidates: &[Candidate], verify: bool) -> Result<Rom, RomError> {
+  let mut image = [0x0; ROM_SIZE];
+  let mut warnings = Vec::new();
+  let mut missing = Vec::new();
+  for chip in set.chips {
+    let named = candidates.iter().find(|candidate| candidate.name.eq_ignore_ascii_case(chip.name));
+    let by_crc = candidates.iter().find(|candidate| candidate.bytes.len() == chip.size && candidate.crc32() == chip.crc32);
+    let candidate = match (named, by_crc) {
+      (Some(named), _) if !verify || named.crc32() == chip.crc32 => named,
+      (named, Some(found)) => {
+        // A named file holding another chip of the set is misnamed, not bad
+        let misnamed = named.is_none_or(|named| set.chips.iter().any(|chip| named.crc32() == chip.crc32));
+        if misnamed {
+          warnings.push(format!("{} found as {}", chip.name, found.name));
+        } else {
+          warnings.push(format!("{} is a bad dump, using {} which matches it", chip.name, found.name));
+        }
+        found
+      },
+      (Some(named), None) => {
+        if named.bytes.len() != chip.size {
+          return Err(RomError::Size { name: named.name.clone(), size: named.bytes.len(), expected: chip.size });
+        }
+        return Err(RomError::BadDump { name: named.name.clone(), crc32: named.crc32(), expected: chip.crc32 });
+      },
+      (None, None) => {
+        missing.push(chip.name);
+        continue;
+      },
+    };
+    if candidate.bytes.len() != chip.size {
+      return Err(RomError::Size { name: candidate.name.clone(), size: candidate.bytes.len(), expected: chip.size });
+    }
+    let sha1 = hex(&Sha1::digest(&candidate.bytes));
+    if verify && sha1 != chip.sha1 {
+      return Err(RomError::BadSha1 { name: candidate.name.clone(), sha1, expected: chip.sha1 });
+    }
+    image[chip.offset..chip.offset + chip.size].copy_from_slice(&candidate.bytes);
+  }
+  if !missing.is_empty() {
+    return Err(RomError::Missing(set.name, missing));
+  }
+  Ok(Rom { image, set, warnings })
+}
+
+fn read_zip(path: &Path) -> Result<Vec<Candidate>, RomError> {
+  let error = |e: zip::result::ZipError| RomError::Zip(path.to_path_buf(), e.to_string());
+  let file = File::open(path).map_err(|e| RomError::Io(path.to_path_buf(), e))?;
+  let mut archive = zip::ZipArchive::new(file).map_err(error)?;
+  let mut candidates = Vec::new();
+  for i in 0..archive.len() {
+    let mut entry = archive.by_index(i).map_err(error)?;
+    if entry.is_dir() {
+      continue;
+    }
+    let name = file_name(Path::new(entry.name()));
+    let mut bytes = Vec::new();
+    entry.read_to_end(&mut bytes).map_err(|e| RomError::Io(path.to_path_buf(), e))?;
+    candidates.push(Candidate { name, bytes });
+  }
+  Ok(candidates)
+}
+
+fn is_zip(path: &Path) -> bool {
+  path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("zip"))
+}
+
+fn file_name(path: &Path) -> String {
+  path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default()
+}
+
+fn hex(bytes: &[u8]) -> String {
+  bytes.iter().map(|db| format!("{:02x}", db)).collect()
+}
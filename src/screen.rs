@@ -1,5 +1,5 @@
 use sdl2::rect::Rect;
-use sdl2::{pixels::Color, event::Event, keyboard::Keycode, render::Canvas, video::Window, EventPump};
+use sdl2::{pixels::Color, event::Event, keyboard::Keycode, render::Canvas, video::Window, AudioSubsystem, EventPump, Sdl};
 use std::sync::{Mutex, Arc};
 use std::sync::mpsc::Sender;
 use std::{thread, time::Duration};
@@ -23,6 +23,7 @@ impl Resolution {
 }
 
 pub struct Screen {
+  context: Sdl,
   canvas: Canvas<Window>,
   res: Resolution,
   events: EventPump,
@@ -37,30 +38,26 @@ impl Screen {
 
     Screen {
       canvas: window.into_canvas().build().unwrap(),
-      events: context.event_pump().unwrap(), 
+      events: context.event_pump().unwrap(),
       res,
       video,
+      context,
     }
   }
 
+  /// Audio of the same SDL context, which can only be initialised once
+  pub fn audio(&self) -> Result<AudioSubsystem, String> {
+    self.context.audio()
+  }
+
   pub fn run(&mut self, tx: Sender<KeycodeState>) {
     'running: loop {
       for event in self.events.poll_iter() {
         match event {
           Event::Quit {..} |
           Event::KeyDown { keycode: Some(Keycode::Escape), .. } => { break 'running; },
-          Event::KeyDown { keycode: Some(Keycode::Left), repeat: false, .. } => { tx.send(KeycodeState { code: Keycode::Left, pressed: true }).unwrap(); },
-          Event::KeyDown { keycode: Some(Keycode::Right), repeat: false, .. } => { tx.send(KeycodeState { code: Keycode::Right, pressed: true }).unwrap(); },
-          Event::KeyDown { keycode: Some(Keycode::Z), repeat: false, .. } => { tx.send(KeycodeState { code: Keycode::Z, pressed: true }).unwrap(); },
-          Event::KeyDown { keycode: Some(Keycode::X), repeat: false, .. } => { tx.send(KeycodeState { code: Keycode::X, pressed: true }).unwrap(); },
-          Event::KeyDown { keycode: Some(Keycode::C), repeat: false, .. } => { tx.send(KeycodeState { code: Keycode::C, pressed: true }).unwrap(); },
-          Event::KeyDown { keycode: Some(Keycode::Space), repeat: false, .. } => { tx.send(KeycodeState { code: Keycode::Space, pressed: true }).unwrap(); },
-          Event::KeyUp { keycode: Some(Keycode::Left), repeat: false, .. } => { tx.send(KeycodeState { code: Keycode::Left, pressed: false }).unwrap(); },
-          Event::KeyUp { keycode: Some(Keycode::Right), repeat: false, .. } => { tx.send(KeycodeState { code: Keycode::Right, pressed: false }).unwrap(); },
-          Event::KeyUp { keycode: Some(Keycode::Z), repeat: false, .. } => { tx.send(KeycodeState { code: Keycode::Z, pressed: false }).unwrap(); },
-          Event::KeyUp { keycode: Some(Keycode::X), repeat: false, .. } => { tx.send(KeycodeState { code: Keycode::X, pressed: false }).unwrap(); },
-          Event::KeyUp { keycode: Some(Keycode::C), repeat: false, .. } => { tx.send(KeycodeState { code: Keycode::C, pressed: false }).unwrap(); },
-          Event::KeyUp { keycode: Some(Keycode::Space), repeat: false, .. } => { tx.send(KeycodeState { code: Keycode::Space, pressed: false }).unwrap(); },
+          Event::KeyDown { keycode: Some(code), repeat: false, .. } => { tx.send(KeycodeState { code, pressed: true }).unwrap(); },
+          Event::KeyUp { keycode: Some(code), repeat: false, .. } => { tx.send(KeycodeState { code, pressed: false }).unwrap(); },
           _ => (),
         }
       }
@@ -73,21 +70,14 @@ impl Screen {
     let scale = self.res.scale as u32;
     self.canvas.set_draw_color(Color::RGB(0, 0, 0));
     self.canvas.clear();
-    let mut cloned_video: Option<[u8; VIDEO_SIZE]> = None;
-    {
-      let video = self.video.lock().unwrap();
-      cloned_video = Some(video.clone());
-    }
-    if let Some(video) = cloned_video {
-      for i in 0..VIDEO_SIZE {
-        let byte = video[i];
-        for j in 0..8 {
-          if (byte >> j) & 0x1 == 1 {
-            self.canvas.set_draw_color(Color::RGB(0xFF, 0xFF, 0xFF));
-            let y = (self.res.height - ((i % 0x20) * 8 + j)) as i32;
-            let x = (i / 0x20) as i32;
-            self.canvas.fill_rect(Rect::new(x*(scale as i32), y*(scale as i32), scale, scale)).unwrap();
-          }
+    let video = *self.video.lock().unwrap();
+    for (i, &byte) in video.iter().enumerate() {
+      for j in 0..8 {
+        if (byte >> j) & 0x1 == 1 {
+          self.canvas.set_draw_color(Color::RGB(0xFF, 0xFF, 0xFF));
+          let y = (self.res.height - ((i % 0x20) * 8 + j)) as i32;
+          let x = (i / 0x20) as i32;
+          self.canvas.fill_rect(Rect::new(x*(scale as i32), y*(scale as i32), scale, scale)).unwrap();
         }
       }
     }
@@ -0,0 +1,43 @@
+/// Space Invaders hardware bit shifter, a 16-bit register the 8080 uses to
+/// draw sprites at any pixel offset without shifting in software.
+///
+/// * OUT 4 (`write_data`) shifts the register right by a byte and puts the
+///   new byte in the top half, so the last two writes are the high and low
+///   bytes.
+/// * OUT 2 (`write_offset`) sets the shift amount from the low 3 bits.
+/// * IN 3 (`read`) returns the 8 bits starting `offset` bits below the top
+///   of the register, bits `15 - offset` down to `8 - offset`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ShiftRegister {
+  value: u16,
+  offset: u8,
+}
+
+impl ShiftRegister {
+  pub fn new() -> ShiftRegister {
+    ShiftRegister::default()
+  }
+
+  /// Port 4
+  pub fn write_data(&mut self, db: u8) {
+    self.value = (db as u16) << 8 | self.value >> 8;
+  }
+
+  /// Port 2, only the low 3 bits are wired
+  pub fn write_offset(&mut self, db: u8) {
+    self.offset = db & 0x7;
+  }
+
+  /// Port 3
+  pub fn read(&self) -> u8 {
+    (self.value >> (8 - self.offset)) as u8
+  }
+
+  pub fn value(&self) -> u16 {
+    self.value
+  }
+
+  pub fn offset(&self) -> u8 {
+    self.offset
+  }
+}
@@ -0,0 +1,269 @@
+use std::mem;
+use std::path::Path;
+use std::sync::mpsc::Receiver;
+
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+use sdl2::AudioSubsystem;
+
+/// Output rate asked of SDL, samples are resampled to the rate it gives
+const RATE: i32 = 44_100;
+
+/// Sounds of the Space Invaders board, numbered as in the usual sample
+/// set, `0.wav` to `8.wav`. `9.wav` for the extended play bell is optional.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sample {
+  Ufo,
+  Shot,
+  PlayerDeath,
+  InvaderDeath,
+  Fleet1,
+  Fleet2,
+  Fleet3,
+  Fleet4,
+  UfoHit,
+  ExtendedPlay,
+}
+
+impl Sample {
+  pub const ALL: [Sample; 10] = [
+    Sample::Ufo, Sample::Shot, Sample::PlayerDeath, Sample::InvaderDeath, Sample::Fleet1,
+    Sample::Fleet2, Sample::Fleet3, Sample::Fleet4, Sample::UfoHit, Sample::ExtendedPlay,
+  ];
+
+  pub fn index(self) -> usize {
+    self as usize
+  }
+
+  pub fn file_name(self) -> String {
+    format!("{}.wav", self.index())
+  }
+
+  /// The UFO repeats for as long as its bit is set
+  pub fn looping(self) -> bool {
+    self == Sample::Ufo
+  }
+}
+
+/// Samples started by bits 0 to 4 of port 3 and of port 5
+const PORT3: [Sample; 5] = [Sample::Ufo, Sample::Shot, Sample::PlayerDeath, Sample::InvaderDeath, Sample::ExtendedPlay];
+const PORT5: [Sample; 5] = [Sample::Fleet1, Sample::Fleet2, Sample::Fleet3, Sample::Fleet4, Sample::UfoHit];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SoundEvent {
+  Start(Sample),
+  /// Only sent for looping samples, the others play to the end
+  Stop(Sample),
+  /// Sound amplifier on or off, off silences everything
+  Amplifier(bool),
+}
+
+/// Sound latches on ports 3 and 5. Sounds start when their bit goes from 0
+/// to 1, so a bit left set does not restart it.
+///
+/// * Port 3: bit 0 UFO, 1 shot, 2 player death, 3 invader death, 4
+///   extended play, 5 amplifier enable.
+/// * Port 5: bits 0 to 3 the four fleet movement notes, 4 UFO hit. Bit 5
+///   flips the screen on cocktail cabinets and is not sound.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SoundLatches {
+  port3: u8,
+  port5: u8,
+}
+
+impl SoundLatches {
+  pub fn new() -> SoundLatches {
+    SoundLatches::default()
+  }
+
+  /// Events caused by writing `db` to port 3 or 5
+  pub fn write(&mut self, port: u8, db: u8) -> Vec<SoundEvent> {
+    let mut events = Vec::new();
+    let (last, samples) = match port {
+      3 => (mem::replace(&mut self.port3, db), PORT3),
+      5 => (mem::replace(&mut self.port5, db), PORT5),
+      _ => return events,
+    };
+    if port == 3 && (last ^ db) & 0x20 != 0 {
+      events.push(SoundEvent::Amplifier(db & 0x20 != 0));
+    }
+    for (bit, &sample) in samples.iter().enumerate() {
+      let mask = 1 << bit;
+      if db & mask != 0 && last & mask == 0 {
+        events.push(SoundEvent::Start(sample));
+      } else if db & mask == 0 && last & mask != 0 && sample.looping() {
+        events.push(SoundEvent::Stop(sample));
+      }
+    }
+    events
+  }
+}
+
+/// Sample being played and how far it has got
+struct Voice {
+  sample: Sample,
+  at: usize,
+}
+
+/// Mixes the samples started by `SoundEvent`s, received from the machine
+/// thread. Runs as the SDL audio callback.
+pub struct Mixer {
+  /// Indexed by `Sample::index`, `None` when the file was missing
+  samples: Vec<Option<Vec<i16>>>,
+  voices: Vec<Voice>,
+  /// Looping samples whose latch bit is set, played while the amplifier is
+  /// on
+  held: Vec<Sample>,
+  amplifier: bool,
+  events: Receiver<SoundEvent>,
+}
+
+impl Mixer {
+  pub fn new(samples: Vec<Option<Vec<i16>>>, events: Receiver<SoundEvent>) -> Mixer {
+    Mixer { samples, voices: Vec::new(), held: Vec::new(), amplifier: false, events }
+  }
+
+  pub fn event(&mut self, event: SoundEvent) {
+    match event {
+      SoundEvent::Start(sample) => {
+        if sample.looping() && !self.held.contains(&sample) {
+          self.held.push(sample);
+        }
+        if self.amplifier {
+          self.start(sample);
+        }
+      },
+      SoundEvent::Stop(sample) => {
+        self.held.retain(|&held| held != sample);
+        self.voices.retain(|voice| voice.sample != sample);
+      },
+      SoundEvent::Amplifier(on) => {
+        self.amplifier = on;
+        if on {
+          // Loops latched while the amplifier was off start now
+          for sample in self.held.clone() {
+            if !self.voices.iter().any(|voice| voice.sample == sample) {
+              self.start(sample);
+            }
+          }
+        } else {
+          self.voices.clear();
+        }
+      },
+    }
+  }
+
+  /// Play `sample` from the start, retriggering restarts it
+  fn start(&mut self, sample: Sample) {
+    self.voices.retain(|voice| voice.sample != sample);
+    self.voices.push(Voice { sample, at: 0 });
+  }
+
+  /// Fill `out` with the sum of the playing samples, after handling the
+  /// events sent since the last call
+  pub fn mix(&mut self, out: &mut [i16]) {
+    while let Ok(event) = self.events.try_recv() {
+      self.event(event);
+    }
+    let mut sums = vec![0i32; out.len()];
+    let samples = &self.samples;
+    self.voices.retain_mut(|voice| {
+      let Some(Some(data)) = samples.get(voice.sample.index()) else {
+        return false;
+      };
+      if data.is_empty() {
+        return false;
+      }
+      for sum in sums.iter_mut() {
+        if voice.at == data.len() {
+          if !voice.sample.looping() {
+            return false;
+          }
+          voice.at = 0;
+        }
+        *sum += data[voice.at] as i32;
+        voice.at += 1;
+      }
+      voice.at < data.len() || voice.sample.looping()
+    });
+    for (out, sum) in out.iter_mut().zip(sums) {
+      *out = sum.clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+    }
+  }
+
+  /// Number of samples playing
+  pub fn playing(&self) -> usize {
+    self.voices.len()
+  }
+}
+
+impl AudioCallback for Mixer {
+  type Channel = i16;
+
+  fn callback(&mut self, out: &mut [i16]) {
+    self.mix(out);
+  }
+}
+
+/// Read `0.wav` to `9.wav` from `dir` as mono at `rate`. Missing or
+/// unreadable files are left out and reported, except the optional `9.wav`.
+pub fn load_samples(dir: &Path, rate: u32) -> (Vec<Option<Vec<i16>>>, Vec<String>) {
+  let mut warnings = Vec::new();
+  let samples = Sample::ALL.iter().map(|&sample| {
+    let path = dir.join(sample.file_name());
+    if sample == Sample::ExtendedPlay && !path.exists() {
+      return None;
+    }
+    match read_wav(&path, rate) {
+      Ok(data) => Some(data),
+      Err(e) => {
+        warnings.push(format!("{}: {}", path.display(), e));
+        None
+      },
+    }
+  }).collect();
+  (samples, warnings)
+}
+
+/// WAV file mixed down to mono and linearly resampled to `rate`
+pub fn read_wav(path: &Path, rate: u32) -> Result<Vec<i16>, hound::Error> {
+  let mut reader = hound::WavReader::open(path)?;
+  let spec = reader.spec();
+  let frames: Vec<i32> = match spec.sample_format {
+    hound::SampleFormat::Float => reader.samples::<f32>().map(|s| s.map(|s| (s * i16::MAX as f32) as i32)).collect::<Result<_, _>>()?,
+    hound::SampleFormat::Int => {
+      let shift = spec.bits_per_sample as i32 - 16;
+      reader.samples::<i32>().map(|s| s.map(|s| if shift >= 0 { s >> shift } else { s << -shift })).collect::<Result<_, _>>()?
+    },
+  };
+  let channels = spec.channels.max(1) as usize;
+  let mono: Vec<i32> = frames.chunks(channels).map(|frame| frame.iter().sum::<i32>() / frame.len() as i32).collect();
+  if mono.is_empty() || spec.sample_rate == rate {
+    return Ok(mono.into_iter().map(|s| s as i16).collect());
+  }
+  let len = (mono.len() as u64 * rate as u64 / spec.sample_rate as u64) as usize;
+  Ok((0..len).map(|i| {
+    let at = i as f64 * spec.sample_rate as f64 / rate as f64;
+    let j = at as usize;
+    let next = mono[(j + 1).min(mono.len() - 1)];
+    let s = mono[j] as f64 + (next - mono[j]) as f64 * at.fract();
+    s as i16
+  }).collect())
+}
+
+/// Open the default output with a `Mixer` playing the samples in `dir`.
+/// Keep the device alive for as long as sound should play.
+pub fn open(audio: &AudioSubsystem, dir: &Path, events: Receiver<SoundEvent>) -> Result<AudioDevice<Mixer>, String> {
+  if !dir.is_dir() {
+    return Err(format!("{}: no sample directory", dir.display()));
+  }
+  let desired = AudioSpecDesired { freq: Some(RATE), channels: Some(1), samples: Some(512) };
+  let device = audio.open_playback(None, &desired, |spec| {
+    let (samples, warnings) = load_samples(dir, spec.freq as u32);
+    for warning in warnings {
+      eprintln!("{}", warning);
+    }
+    Mixer::new(samples, events)
+  })?;
+  device.resume();
+  Ok(device)
+}
@@ -0,0 +1,166 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::{fs, io};
+
+use crate::disasm::{Instruction, Operand};
+use crate::trace;
+
+/// Annotated map of the Space Invaders ROM and RAM
+const SPACE_INVADERS: &str = include_str!("../symbols/space-invaders.txt");
+
+pub struct Symbol {
+  pub name: String,
+  pub comment: Option<String>,
+}
+
+/// Names for addresses, read from symbol files
+#[derive(Default)]
+pub struct Symbols {
+  by_address: BTreeMap<u16, Symbol>,
+  /// Upper case name to address
+  by_name: HashMap<String, u16>,
+}
+
+impl Symbols {
+  pub fn new() -> Symbols {
+    Symbols::default()
+  }
+
+  /// The bundled Space Invaders map
+  pub fn space_invaders() -> Symbols {
+    Symbols::parse(SPACE_INVADERS).expect("bundled symbol map")
+  }
+
+  /// Load a symbol file, `.sym` files in the `.sym` format and anything else
+  /// as `ADDR NAME [comment]` lines
+  pub fn load(path: &Path) -> io::Result<Symbols> {
+    let text = fs::read_to_string(path)?;
+    let sym = path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("sym"));
+    let symbols = if sym { Symbols::parse_sym(&text) } else { Symbols::parse(&text) };
+    symbols.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+  }
+
+  /// One `ADDR NAME [comment]` per line. The address is hex with an optional
+  /// `0x`, `$` or `H`, the comment may start with `;`. Blank lines and lines
+  /// starting with `;` or `#` are skipped.
+  pub fn parse(text: &str) -> Result<Symbols, String> {
+    let mut symbols = Symbols::new();
+    for (i, line) in text.lines().enumerate() {
+      let line = line.trim();
+      if line.is_empty() || line.starts_with(';') || line.starts_with('#') {
+        continue;
+      }
+      let (address, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+      let rest = rest.trim_start();
+      let (name, comment) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+      let comment = Some(comment.trim().trim_start_matches(';').trim()).filter(|comment| !comment.is_empty());
+      let hblb = parse_address(address).ok_or(format!("line {}: invalid address {}", i + 1, address))?;
+      symbols.insert(hblb, name, comment).map_err(|e| format!("line {}: {}", i + 1, e))?;
+    }
+    Ok(symbols)
+  }
+
+  /// `.sym` files as written by CP/M assemblers and linkers, `ADDR NAME`
+  /// pairs with any number to a line, or `NAME EQU ADDR` lines
+  pub fn parse_sym(text: &str) -> Result<Symbols, String> {
+    let mut symbols = Symbols::new();
+    for (i, line) in text.lines().enumerate() {
+      let line = line.split(';').next().unwrap_or("");
+      let fields: Vec<&str> = line.split_whitespace().collect();
+      let error = |e: String| format!("line {}: {}", i + 1, e);
+      if let [name, equ, address] = fields[..] {
+        if equ.eq_ignore_ascii_case("EQU") || equ == "=" {
+          let hblb = parse_address(address).ok_or(error(format!("invalid address {}", address)))?;
+          symbols.insert(hblb, name.trim_end_matches(':'), None).map_err(error)?;
+          continue;
+        }
+      }
+      if !fields.len().is_multiple_of(2) {
+        return Err(error(String::from("expected address and name pairs")));
+      }
+      for pair in fields.chunks(2) {
+        let hblb = parse_address(pair[0]).ok_or(error(format!("invalid address {}", pair[0])))?;
+        symbols.insert(hblb, pair[1], None).map_err(error)?;
+      }
+    }
+    Ok(symbols)
+  }
+
+  /// Add a name. Names must be valid assembler identifiers and unique.
+  pub fn insert(&mut self, hblb: u16, name: &str, comment: Option<&str>) -> Result<(), String> {
+    let valid = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') &&
+      name.chars().all(|c| c.is_ascii_alphanumeric() || "_?@.".contains(c));
+    if !valid {
+      return Err(format!("invalid name {:?}", name));
+    }
+    if self.by_name.insert(name.to_uppercase(), hblb).is_some() {
+      return Err(format!("duplicate name {}", name));
+    }
+    if let Some(old) = self.by_address.insert(hblb, Symbol { name: name.to_string(), comment: comment.map(String::from) }) {
+      self.by_name.remove(&old.name.to_uppercase());
+    }
+    Ok(())
+  }
+
+  pub fn len(&self) -> usize {
+    self.by_address.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.by_address.is_empty()
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item = (u16, &Symbol)> {
+    self.by_address.iter().map(|(&hblb, symbol)| (hblb, symbol))
+  }
+
+  pub fn name(&self, hblb: u16) -> Option<&str> {
+    self.by_address.get(&hblb).map(|symbol| symbol.name.as_str())
+  }
+
+  pub fn comment(&self, hblb: u16) -> Option<&str> {
+    self.by_address.get(&hblb).and_then(|symbol| symbol.comment.as_deref())
+  }
+
+  /// Address of a name, ignoring case
+  pub fn address(&self, name: &str) -> Option<u16> {
+    self.by_name.get(&name.to_uppercase()).copied()
+  }
+
+  /// Address typed by a user: a name, `NAME+OFFSET` with a hex offset, or a
+  /// hex address. Names win over hex, so `ADD` is a name if one exists.
+  pub fn resolve(&self, text: &str) -> Option<u16> {
+    let text = text.trim();
+    if let Some(hblb) = self.address(text) {
+      return Some(hblb);
+    }
+    if let Some((name, offset)) = text.split_once('+') {
+      return Some(self.address(name.trim())?.wrapping_add(parse_address(offset.trim())?));
+    }
+    parse_address(text)
+  }
+
+  /// Name of an address, or the default `LXXXX` label
+  pub fn label(&self, hblb: u16) -> String {
+    self.name(hblb).map(String::from).unwrap_or_else(|| trace::label(hblb))
+  }
+
+  /// Instruction text with named addresses replaced by their names
+  pub fn instruction(&self, instruction: &Instruction) -> String {
+    let operands: Vec<String> = instruction.operands.iter().map(|operand| match *operand {
+      Operand::Addr(hblb) => self.name(hblb).map(String::from).unwrap_or(operand.to_string()),
+      _ => operand.to_string(),
+    }).collect();
+    if operands.is_empty() {
+      instruction.mnemonic.to_string()
+    } else {
+      format!("{} {}", instruction.mnemonic, operands.join(","))
+    }
+  }
+}
+
+/// Hex address with an optional `0x`, `$` or `H`
+pub(crate) fn parse_address(text: &str) -> Option<u16> {
+  let text = text.trim_start_matches("0x").trim_start_matches("0X").trim_start_matches('$').trim_end_matches(['H', 'h']);
+  u16::from_str_radix(text, 16).ok()
+}
@@ -0,0 +1,224 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use crate::bus::Bus;
+use crate::disasm::{self, Instruction, Operand};
+use crate::symbols::Symbols;
+
+/// ROM image placed at an origin, read-only
+pub struct Image {
+  pub origin: u16,
+  pub bytes: Vec<u8>,
+}
+
+impl Image {
+  pub fn new(origin: u16, bytes: Vec<u8>) -> Image {
+    Image { origin, bytes }
+  }
+
+  /// Address one past the last byte
+  pub fn end(&self) -> usize {
+    self.origin as usize + self.bytes.len()
+  }
+
+  pub fn contains(&self, hblb: usize) -> bool {
+    (self.origin as usize..self.end()).contains(&hblb)
+  }
+}
+
+impl Bus for Image {
+  fn read(&self, hblb: u16) -> u8 {
+    (hblb as usize).checked_sub(self.origin as usize).and_then(|i| self.bytes.get(i)).copied().unwrap_or(0)
+  }
+
+  fn write(&mut self, _hblb: u16, _db: u8) {}
+}
+
+/// Code found by following control flow through an image. Bytes that are
+/// never reached are treated as data.
+pub struct Trace {
+  pub instructions: BTreeMap<u16, Instruction>,
+  /// Jump and call targets
+  pub labels: BTreeSet<u16>,
+}
+
+impl Trace {
+  /// Follow every path from `entries`. Entries that land inside an
+  /// instruction already decoded are skipped, as they would give
+  /// overlapping code.
+  pub fn new(image: &Image, entries: &[u16]) -> Trace {
+    let mut trace = Trace { instructions: BTreeMap::new(), labels: BTreeSet::new() };
+    let mut owner: BTreeMap<usize, u16> = BTreeMap::new();
+
+    for &entry in entries {
+      if owner.contains_key(&(entry as usize)) || !image.contains(entry as usize) {
+        continue;
+      }
+      let mut pending = vec![entry];
+      while let Some(address) = pending.pop() {
+        if trace.instructions.contains_key(&address) {
+          continue;
+        }
+        let instruction = disasm::decode(image, address);
+        let span = address as usize..address as usize + instruction.len();
+        if !image.contains(span.end - 1) || span.clone().any(|i| owner.contains_key(&i)) {
+          continue;
+        }
+        for i in span {
+          owner.insert(i, address);
+        }
+        if let Some(target) = instruction.target() {
+          trace.labels.insert(target);
+        }
+        pending.extend(instruction.successors().into_iter().rev());
+        trace.instructions.insert(address, instruction);
+      }
+    }
+    trace.labels.retain(|target| trace.instructions.contains_key(target));
+    trace
+  }
+
+  /// Entry points of an 8080 ROM: reset and the eight RST vectors
+  pub fn vectors(origin: u16) -> Vec<u16> {
+    (0..8).map(|n| origin.wrapping_add(n << 3)).collect()
+  }
+
+  /// Listing in the `ADDR  MNEMONIC  OPERAND` layout, with a label line before
+  /// every jump or call target and named address, and unreached bytes as DB
+  pub fn listing(&self, image: &Image, symbols: &Symbols) -> String {
+    let mut out = String::new();
+    let mut hblb = image.origin as usize;
+    while hblb < image.end() {
+      if self.is_labelled(symbols, hblb as u16) {
+        match symbols.comment(hblb as u16) {
+          Some(comment) => writeln!(out, "{}:\t; {}", symbols.label(hblb as u16), comment).unwrap(),
+          None => writeln!(out, "{}:", symbols.label(hblb as u16)).unwrap(),
+        }
+      }
+      match self.instructions.get(&(hblb as u16)) {
+        Some(instruction) => {
+          let (field, operand) = log_fields(instruction, symbols);
+          writeln!(out, "{:04X}\t{:>8}\t{}", hblb, field, operand).unwrap();
+          hblb += instruction.len();
+        },
+        None => {
+          let start = hblb;
+          let mut data = Vec::new();
+          loop {
+            data.push(format!("${:02X}", image.read(hblb as u16)));
+            hblb += 1;
+            let next = hblb as u16;
+            if hblb >= image.end() || data.len() == 8 || self.instructions.contains_key(&next) || self.is_labelled(symbols, next) {
+              break;
+            }
+          }
+          writeln!(out, "{:04X}\t{:>8}\t{}", start, "DB", data.join(",")).unwrap();
+        },
+      }
+    }
+    out
+  }
+
+  /// Source that `asm::assemble` turns back into the same image. Jump and
+  /// call targets get labels, unreached bytes and undocumented opcodes are
+  /// written as DB. Names that do not fall on the start of a line are
+  /// defined with EQU.
+  pub fn source(&self, image: &Image, symbols: &Symbols) -> String {
+    let mut body = String::new();
+    let mut placed = BTreeSet::new();
+    let mut hblb = image.origin as usize;
+    while hblb < image.end() {
+      if self.is_labelled(symbols, hblb as u16) {
+        placed.insert(hblb as u16);
+        match symbols.comment(hblb as u16) {
+          Some(comment) => writeln!(body, "{}:\t\t; {}", symbols.label(hblb as u16), comment).unwrap(),
+          None => writeln!(body, "{}:", symbols.label(hblb as u16)).unwrap(),
+        }
+      }
+      match self.instructions.get(&(hblb as u16)) {
+        Some(instruction) if instruction.documented => {
+          let operands: Vec<String> = instruction.operands.iter().map(|operand| match *operand {
+            Operand::Addr(target) if self.labels.contains(&target) => symbols.label(target),
+            Operand::Addr(target) => symbols.name(target).map(String::from).unwrap_or(operand.to_string()),
+            _ => operand.to_string(),
+          }).collect();
+          if operands.is_empty() {
+            writeln!(body, "\t{}", instruction.mnemonic).unwrap();
+          } else {
+            writeln!(body, "\t{}\t{}", instruction.mnemonic, operands.join(",")).unwrap();
+          }
+          hblb += instruction.len();
+        },
+        _ => {
+          let mut data = Vec::new();
+          loop {
+            data.push(disasm::hex8(image.read(hblb as u16)));
+            hblb += 1;
+            let next = hblb as u16;
+            let code = self.instructions.get(&next).is_some_and(|instruction| instruction.documented);
+            if hblb >= image.end() || data.len() == 8 || code || self.is_labelled(symbols, next) {
+              break;
+            }
+          }
+          writeln!(body, "\tDB\t{}", data.join(",")).unwrap();
+        },
+      }
+    }
+
+    let mut out = String::new();
+    for (hblb, symbol) in symbols.iter().filter(|(hblb, _)| !placed.contains(hblb)) {
+      match &symbol.comment {
+        Some(comment) => writeln!(out, "{}\tEQU\t{}\t; {}", symbol.name, disasm::hex16(hblb), comment).unwrap(),
+        None => writeln!(out, "{}\tEQU\t{}", symbol.name, disasm::hex16(hblb)).unwrap(),
+      }
+    }
+    writeln!(out, "\tORG\t{}", disasm::hex16(image.origin)).unwrap();
+    out + &body
+  }
+
+  /// A line at `hblb` gets a label: it is a jump or call target, or named
+  fn is_labelled(&self, symbols: &Symbols, hblb: u16) -> bool {
+    self.labels.contains(&hblb) || symbols.name(hblb).is_some()
+  }
+}
+
+/// Default label for an address
+pub fn label(hblb: u16) -> String {
+  format!("L{:04X}", hblb)
+}
+
+/// Mnemonic column and operand column as laid out in log.txt: register
+/// operands stay with the mnemonic, immediates show as `#` there and their
+/// value goes in the operand column
+fn log_fields(instruction: &Instruction, symbols: &Symbols) -> (String, String) {
+  let mut names = Vec::new();
+  let mut value = String::new();
+  for operand in &instruction.operands {
+    match *operand {
+      Operand::Reg(name) => names.push(name.to_string()),
+      Operand::Pair(name) => names.push(match name {
+        "B" => "BC",
+        "D" => "DE",
+        "H" => "HL",
+        _ => name,
+      }.to_string()),
+      Operand::Rst(n) => names.push(format!("{:02}", n)),
+      Operand::Imm8(db) => {
+        names.push(String::from("#"));
+        value = format!("${:02X}", db);
+      },
+      Operand::Imm16(hblb) => {
+        names.push(String::from("#"));
+        value = format!("${:04X}", hblb);
+      },
+      Operand::Port(db) => value = format!("${:02X}", db),
+      Operand::Addr(hblb) => value = symbols.name(hblb).map(String::from).unwrap_or(format!("${:04X}", hblb)),
+    }
+  }
+  let field = if names.is_empty() {
+    instruction.mnemonic.to_string()
+  } else {
+    format!("{} {}", instruction.mnemonic, names.join(","))
+  };
+  (field, value)
+}
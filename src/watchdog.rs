@@ -0,0 +1,45 @@
+/// Frames the Space Invaders watchdog waits for a kick, about 4.3 seconds
+/// at the board's 60 Hz
+pub const TIMEOUT_FRAMES: u32 = 255;
+
+/// Counter that resets the board unless the program writes to it in time.
+/// On Space Invaders any OUT to port 6 kicks it, and the ROM does so from
+/// its main loop, so a hung or runaway program gets restarted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchdog {
+  timeout: u32,
+  frames: u32,
+}
+
+impl Default for Watchdog {
+  fn default() -> Watchdog {
+    Watchdog::new(TIMEOUT_FRAMES)
+  }
+}
+
+impl Watchdog {
+  pub fn new(timeout: u32) -> Watchdog {
+    Watchdog { timeout, frames: 0 }
+  }
+
+  /// Written to by the program, start counting again
+  pub fn kick(&mut self) {
+    self.frames = 0;
+  }
+
+  /// Count a frame, returns true once the timeout passes without a kick
+  /// and starts counting again
+  pub fn frame(&mut self) -> bool {
+    self.frames += 1;
+    if self.frames > self.timeout {
+      self.frames = 0;
+      return true;
+    }
+    false
+  }
+
+  /// Frames since the last kick
+  pub fn frames(&self) -> u32 {
+    self.frames
+  }
+}
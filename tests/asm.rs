@@ -0,0 +1,102 @@
+//! Checks the assembler against the disassembler and runs small programs
+//! written as source on `Processor`.
+
+mod common;
+
+use invaders::asm::assemble;
+use invaders::bus::{Bus, NullIo};
+use invaders::processor::{HaltPolicy, Processor};
+use invaders::symbols::Symbols;
+use invaders::trace::{Image, Trace};
+
+use common::rom;
+
+#[test]
+fn space_invaders_round_trip() {
+  let image = Image::new(0, rom());
+  let source = Trace::new(&image, &Trace::vectors(0)).source(&image, &Symbols::new());
+  let program = assemble(&source).unwrap();
+  assert_eq!(program.origin, 0);
+  assert!(program.bytes == image.bytes, "reassembled ROM differs");
+}
+
+#[test]
+fn directives_and_expressions() {
+  let program = assemble("
+COUNT   EQU 3
+        ORG 100H
+START:  MVI A,COUNT*2+1     ; 7
+        LXI H,TABLE
+        JMP START
+TABLE   DB 'AB',0,-1,'''',LOW 1234H
+        DW TABLE, $, DONE-START
+        DS 2
+        DB (1 SHL 4) OR 0FH, 101B, 17Q, HIGH 1234H
+DONE:
+").unwrap();
+  assert_eq!(program.origin, 0x100);
+  assert_eq!(program.symbols["TABLE"], 0x108);
+  assert_eq!(program.bytes, [
+    0x3E, 0x07, 0x21, 0x08, 0x01, 0xC3, 0x00, 0x01,
+    0x41, 0x42, 0x00, 0xFF, 0x27, 0x34,
+    0x08, 0x01, 0x0E, 0x01, 0x1A, 0x00,
+    0x00, 0x00,
+    0x1F, 0x05, 0x0F, 0x12,
+  ]);
+}
+
+#[test]
+fn errors_report_the_line() {
+  let error = assemble("  NOP\n  MVI A,UNKNOWN\n").unwrap_err();
+  assert_eq!(error.line, 2);
+  assert!(assemble("  MVI A,100H").is_err());
+  assert!(assemble("X: NOP\nX: NOP").is_err());
+}
+
+/// Assemble at 0 and run until HLT
+fn run(source: &str) -> Processor {
+  let program = assemble(source).unwrap();
+  let mut cpu = Processor::flat();
+  for (i, &db) in program.bytes.iter().enumerate() {
+    cpu.mem.write(program.origin + i as u16, db);
+  }
+  cpu.halt_policy = HaltPolicy::Stop;
+  let mut state = cpu.state();
+  state.sp = 0xFF00;
+  cpu.set_state(&state);
+  for _ in 0..10_000 {
+    if cpu.is_stopped() {
+      return cpu;
+    }
+    cpu.exec(&mut NullIo);
+  }
+  panic!("program did not halt");
+}
+
+#[test]
+fn daa_adjusts_bcd_addition() {
+  let cpu = run("
+        MVI A,38H
+        ADI 45H
+        DAA
+        HLT
+");
+  assert_eq!(cpu.state().a, 0x83);
+  assert!(!cpu.state().flags.cy);
+}
+
+#[test]
+fn call_and_push_psw() {
+  let cpu = run("
+        LXI B,1234H
+        CALL SWAP
+        HLT
+SWAP:   PUSH B
+        POP PSW
+        RET
+");
+  let state = cpu.state();
+  assert_eq!(state.a, 0x12);
+  assert_eq!(state.psw() & 0xFF, 0x34 & 0xD7 | 0x02);
+  assert_eq!(state.sp, 0xFF00);
+}
@@ -0,0 +1,50 @@
+//! Checks the bits of the cabinet input ports.
+
+use invaders::cabinet::{CabinetInputs, Controls, DipSwitches};
+
+#[test]
+fn idle_cabinet() {
+  let inputs = CabinetInputs::new();
+  assert_eq!(inputs.port0(), 0x0E);
+  assert_eq!(inputs.port1(), 0x08);
+  assert_eq!(inputs.port2(), 0x00);
+  assert_eq!(inputs.read(3), None);
+}
+
+#[test]
+fn buttons() {
+  let mut inputs = CabinetInputs::new();
+  inputs.coin = true;
+  assert_eq!(inputs.port1(), 0x09);
+  inputs.coin = false;
+  inputs.p2_start = true;
+  assert_eq!(inputs.port1(), 0x0A);
+  inputs.p2_start = false;
+  inputs.p1_start = true;
+  assert_eq!(inputs.port1(), 0x0C);
+  inputs.p1_start = false;
+
+  inputs.p1 = Controls { fire: true, left: false, right: true };
+  assert_eq!(inputs.port1(), 0x58);
+  assert_eq!(inputs.port0(), 0x5E);
+  assert_eq!(inputs.port2(), 0x00);
+
+  inputs.p1 = Controls::default();
+  inputs.p2 = Controls { fire: false, left: true, right: false };
+  inputs.tilt = true;
+  assert_eq!(inputs.port2(), 0x24);
+  assert_eq!(inputs.read(1), Some(0x08));
+}
+
+#[test]
+fn dip_switches() {
+  let mut inputs = CabinetInputs::new();
+  for (ships, bits) in [(3, 0x00), (4, 0x01), (5, 0x02), (6, 0x03)] {
+    inputs.dips.ships = ships;
+    assert_eq!(inputs.port2(), bits);
+  }
+  inputs.dips = DipSwitches { ships: 3, extra_ship_at_1000: true, coin_info: true };
+  assert_eq!(inputs.port2(), 0x08);
+  inputs.dips = DipSwitches { ships: 3, extra_ship_at_1000: false, coin_info: false };
+  assert_eq!(inputs.port2(), 0x80);
+}
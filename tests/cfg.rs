@@ -0,0 +1,110 @@
+//! Checks the control-flow graph and that the disassembler decodes every
+//! opcode the way `Processor` executes it.
+
+mod common;
+
+use invaders::asm::assemble;
+use invaders::bus::{Bus, NullIo};
+use invaders::cfg::{Cfg, Edge};
+use invaders::disasm::{self, Flow};
+use invaders::processor::{Flags, Processor};
+use invaders::symbols::Symbols;
+use invaders::trace::{Image, Trace};
+
+use common::rom;
+
+#[test]
+fn decode_matches_processor() {
+  for opcode in 0..=0xFF {
+    for set in [false, true] {
+      let mut cpu = Processor::flat();
+      for (i, db) in [opcode, 0x34, 0x12].into_iter().enumerate() {
+        cpu.mem.write(0x1000 + i as u16, db);
+      }
+      cpu.mem.write(0x8000, 0x78);
+      cpu.mem.write(0x8001, 0x56);
+      let mut state = cpu.state();
+      state.pc = 0x1000;
+      state.sp = 0x8000;
+      state.set_hl(0x4321);
+      state.flags = Flags { s: set, z: set, ac: set, p: set, cy: set };
+      cpu.set_state(&state);
+
+      let instruction = disasm::decode(&cpu.mem, 0x1000);
+      let cycles = cpu.exec(&mut NullIo);
+      let pc = cpu.state().pc;
+      let next = instruction.next();
+      let expected = match instruction.flow {
+        Flow::Next | Flow::Halt => vec![next],
+        Flow::Jump(target) | Flow::Call(target) => vec![target],
+        Flow::Branch(target) | Flow::CondCall(target) => vec![next, target],
+        Flow::Return => vec![0x5678],
+        Flow::CondReturn => vec![next, 0x5678],
+        Flow::Indirect => vec![0x4321],
+      };
+      assert!(expected.contains(&pc), "{:02X} {} went to {:04X}", opcode, instruction, pc);
+      let taken = pc != next && matches!(instruction.flow, Flow::CondCall(_) | Flow::CondReturn);
+      let expected_cycles = if taken { instruction.taken_cycles } else { instruction.cycles };
+      assert_eq!(cycles, expected_cycles as usize, "{:02X} {}", opcode, instruction);
+    }
+  }
+}
+
+#[test]
+fn jump_table() {
+  let program = assemble("
+        ORG 0
+        MVI A,1
+        CALL DISPATCH
+        HLT
+DISPATCH:
+        LXI H,TABLE
+        ADD A
+        MOV E,A
+        MVI D,0
+        DAD D
+        MOV E,M
+        INX H
+        MOV D,M
+        XCHG
+        PCHL
+TABLE:  DW ZERO, ONE, TWO
+ZERO:   RET
+ONE:    ORA A
+        RZ
+        RET
+TWO:    JMP ZERO
+        DB 0FFH, 0FFH
+").unwrap();
+  let image = Image::new(program.origin, program.bytes);
+  let at = |name: &str| program.symbols[name];
+  let cfg = Cfg::new(&image, &Trace::vectors(0)[..1]);
+
+  let pchl = at("TABLE") - 1;
+  assert_eq!(cfg.tables[&pchl], [at("ZERO"), at("ONE"), at("TWO")]);
+  assert!(cfg.subroutines.contains(&at("DISPATCH")));
+  let dispatch = &cfg.blocks[&at("DISPATCH")];
+  assert_eq!(dispatch.edges, [Edge::Table(at("ZERO")), Edge::Table(at("ONE")), Edge::Table(at("TWO"))]);
+  let one = &cfg.blocks[&at("ONE")];
+  assert!(one.returns);
+  assert_eq!(one.edges, [Edge::Fall(at("ONE") + 2)]);
+  assert_eq!(cfg.subroutine(at("DISPATCH")).len(), 5);
+  assert_eq!(cfg.blocks[&0].calls, [at("DISPATCH")]);
+}
+
+#[test]
+fn space_invaders_blocks_cover_the_trace() {
+  let image = Image::new(0, rom());
+  let cfg = Cfg::new(&image, &Trace::vectors(0));
+  let mut covered: Vec<u16> = cfg.blocks.values().flat_map(|block| block.instructions.iter().copied()).collect();
+  covered.sort();
+  assert_eq!(covered, cfg.trace.instructions.keys().copied().collect::<Vec<u16>>());
+
+  let symbols = Symbols::space_invaders();
+  let dot = cfg.subroutine_dot(symbols.address("PrintMessage").unwrap(), &symbols);
+  assert!(dot.starts_with("digraph \"PrintMessage\""));
+  assert!(dot.contains("CALL DrawChar"));
+  let dot = cfg.dot(&symbols);
+  assert!(dot.contains("subgraph cluster_0010 {\n    label=\"ScanLine224\";"));
+  assert!(dot.contains("b0000 -> b18D4 [color=blue];"));
+}
@@ -0,0 +1,69 @@
+//! Command line switches, checked by running the binary.
+
+mod common;
+
+use std::process::{Command, Output};
+
+fn invaders(args: &[&str]) -> Output {
+  Command::new(env!("CARGO_BIN_EXE_invaders")).args(args).output().unwrap()
+}
+
+fn rom() -> String {
+  common::rom_path().display().to_string()
+}
+
+fn stderr(output: &Output) -> String {
+  String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn disasm_rejects_bad_addresses() {
+  let output = invaders(&["disasm", &rom(), "--origin", "zz"]);
+  assert_eq!(stderr(&output), "--origin: invalid address zz\n");
+  assert!(output.stdout.is_empty());
+
+  let output = invaders(&["disasm", &rom(), "--entry", "10000"]);
+  assert_eq!(stderr(&output), "--entry: invalid address 10000\n");
+  assert!(output.stdout.is_empty());
+
+  let output = invaders(&["disasm", &rom(), "--entry"]);
+  assert_eq!(stderr(&output), "--entry: missing address\n");
+  assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn disasm_accepts_address_forms() {
+  for origin in ["0", "0x0", "$0", "0H"] {
+    let output = invaders(&["disasm", &rom(), "--origin", origin, "--entry", "$18DC"]);
+    assert!(stderr(&output).is_empty(), "{}", stderr(&output));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("18DC"));
+  }
+}
+
+#[test]
+fn cfg_rejects_bad_addresses() {
+  let output = invaders(&["cfg", &rom(), "--origin", "0x"]);
+  assert_eq!(stderr(&output), "--origin: invalid address 0x\n");
+  assert!(output.stdout.is_empty());
+
+  let output = invaders(&["cfg", &rom(), "--entry", "START"]);
+  assert_eq!(stderr(&output), "--entry: invalid address START\n");
+  assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn load_and_entry_reject_bad_addresses() {
+  let output = invaders(&[&rom(), "--load", "8000:"]);
+  assert_eq!(stderr(&output), "--load: invalid address 8000:\n");
+
+  let output = invaders(&[&rom(), "--load", "0x100", "--entry", "-1"]);
+  assert_eq!(stderr(&output), "--entry: invalid address -1\n");
+}
+
+#[test]
+fn bad_symbol_file_stops() {
+  let missing = common::scratch("cli/symbols").join("missing.sym");
+  let output = invaders(&["disasm", &rom(), "--symbols", &missing.display().to_string()]);
+  assert!(stderr(&output).starts_with(&format!("{}: ", missing.display())), "{}", stderr(&output));
+  assert!(output.stdout.is_empty());
+}
@@ -0,0 +1,36 @@
+//! Fixtures shared by the integration tests.
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use invaders::asm::assemble;
+use invaders::bus::Bus;
+use invaders::processor::Processor;
+
+/// Flat processor with `source` assembled into memory
+pub fn load(source: &str) -> Processor {
+  let program = assemble(source).unwrap();
+  let mut cpu = Processor::flat();
+  for (i, &db) in program.bytes.iter().enumerate() {
+    cpu.mem.write(program.origin.wrapping_add(i as u16), db);
+  }
+  cpu
+}
+
+/// The Space Invaders ROM bundled with the repository
+pub fn rom_path() -> PathBuf {
+  Path::new(env!("CARGO_MANIFEST_DIR")).join("space-invaders.rom")
+}
+
+pub fn rom() -> Vec<u8> {
+  fs::read(rom_path()).unwrap()
+}
+
+/// Empty scratch directory for one test, `name` may have several levels
+pub fn scratch(name: &str) -> PathBuf {
+  let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join(name);
+  let _ = fs::remove_dir_all(&dir);
+  fs::create_dir_all(&dir).unwrap();
+  dir
+}
@@ -0,0 +1,52 @@
+//! Checks the DIP switch settings read from config files.
+
+mod common;
+
+use std::fs;
+use std::path::Path;
+
+use invaders::cabinet::DipSwitches;
+use invaders::config::Config;
+
+use common::scratch;
+
+#[test]
+fn dip_switches_from_a_file() {
+  let config = Config::parse("
+[dip_switches]
+ships = 5
+extra_ship_at = 1000
+coin_info = false
+").unwrap();
+  assert_eq!(config.dips, DipSwitches { ships: 5, extra_ship_at_1000: true, coin_info: false });
+
+  let config = Config::parse("[dip_switches]\nships = 6\n").unwrap();
+  assert_eq!(config.dips, DipSwitches { ships: 6, ..DipSwitches::default() });
+  assert_eq!(Config::parse("").unwrap(), Config::default());
+}
+
+#[test]
+fn sample_directory() {
+  let config = Config::parse("[sound]\nsamples = \"/usr/share/invaders\"\n").unwrap();
+  assert_eq!(config.samples.as_deref(), Some(Path::new("/usr/share/invaders")));
+
+  let dir = scratch("config");
+  fs::write(dir.join("invaders.toml"), "[sound]\nsamples = \"wav\"\n").unwrap();
+  let config = Config::load(&dir.join("invaders.toml")).unwrap();
+  assert_eq!(config.samples, Some(dir.join("wav")));
+}
+
+#[test]
+fn invalid_settings() {
+  assert_eq!(Config::parse("[dip_switches]\nships = 7\n").unwrap_err(), "7 ships, expected 3 to 6");
+  assert_eq!(Config::parse("[dip_switches]\nextra_ship_at = 2000\n").unwrap_err(), "extra ship at 2000, expected 1000 or 1500");
+  assert!(Config::parse("[dip_switches]\nlives = 3\n").unwrap_err().contains("unknown field `lives`"));
+  assert!(Config::parse("[dip_switches]\ncoin_info = 1\n").is_err());
+
+  let mut dips = DipSwitches::default();
+  assert!(dips.set_ships(2).is_err());
+  assert!(dips.set_extra_ship_at(1200).is_err());
+  assert_eq!(dips, DipSwitches::default());
+  dips.set_extra_ship_at(1500).unwrap();
+  assert!(!dips.extra_ship_at_1000);
+}
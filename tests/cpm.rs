@@ -0,0 +1,157 @@
+//! CP/M programs run against the host BDOS.
+
+mod common;
+
+use std::fs;
+use std::path::PathBuf;
+
+use invaders::asm::assemble;
+use invaders::bus::Bus;
+use invaders::cpm::Cpm;
+
+use common::scratch;
+
+/// Assemble at 100H and set up CP/M with console output collected
+fn cpm(source: &str, dir: PathBuf, args: &[&str]) -> Cpm {
+  let program = assemble(source).unwrap();
+  assert_eq!(program.origin, 0x0100);
+  let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+  let mut cpm = Cpm::new(&program.bytes, dir, &args).unwrap();
+  cpm.console = Some(Vec::new());
+  cpm
+}
+
+#[test]
+fn console_output_and_version() {
+  let mut cpm = cpm("
+        ORG 100H
+        MVI C,0CH
+        CALL 5
+        SHLD 200H
+        MVI C,9
+        LXI D,MSG
+        CALL 5
+        MVI C,2
+        MVI E,'!'
+        CALL 5
+        JMP 0
+MSG:    DB 'HELLO, WORLD$'
+", scratch("cpm/console"), &[]);
+  cpm.run();
+  assert_eq!(cpm.console.as_deref(), Some(&b"HELLO, WORLD!"[..]));
+  // CP/M 2.2
+  assert_eq!((cpm.cpu.mem.read(0x0200), cpm.cpu.mem.read(0x0201)), (0x22, 0x00));
+}
+
+#[test]
+fn file_written_and_read_back() {
+  let dir = scratch("cpm/files");
+  let mut cpm = cpm("
+        ORG 100H
+FCB     EQU 5CH
+        MVI C,16H
+        LXI D,FCB
+        CALL 5
+        STA 300H
+        MVI C,1AH
+        LXI D,400H
+        CALL 5
+        MVI C,15H
+        LXI D,FCB
+        CALL 5
+        STA 301H
+        MVI C,10H
+        LXI D,FCB
+        CALL 5
+        XRA A
+        STA FCB+12
+        STA FCB+32
+        MVI C,0FH
+        LXI D,FCB
+        CALL 5
+        STA 302H
+        MVI C,1AH
+        LXI D,500H
+        CALL 5
+        MVI C,14H
+        LXI D,FCB
+        CALL 5
+        STA 303H
+        MVI C,14H
+        LXI D,FCB
+        CALL 5
+        STA 304H
+        JMP 0
+", dir.clone(), &["test.dat"]);
+  let record: Vec<u8> = (0..128).collect();
+  for (i, &db) in record.iter().enumerate() {
+    cpm.cpu.mem.write(0x0400 + i as u16, db);
+  }
+  cpm.run();
+
+  // Make, write, open and read succeed, the second read is past the end
+  let results: Vec<u8> = (0..5).map(|i| cpm.cpu.mem.read(0x0300 + i)).collect();
+  assert_eq!(results, [0x00, 0x00, 0x00, 0x00, 0x01]);
+  assert_eq!(fs::read(dir.join("TEST.DAT")).unwrap(), record);
+  let read: Vec<u8> = (0..128).map(|i| cpm.cpu.mem.read(0x0500 + i)).collect();
+  assert_eq!(read, record);
+}
+
+#[test]
+fn console_input() {
+  let mut cpm = cpm("
+        ORG 100H
+        MVI C,1
+        CALL 5
+        STA 200H
+        MVI A,8
+        STA 300H
+        MVI C,0AH
+        LXI D,300H
+        CALL 5
+        MVI C,1
+        CALL 5
+        STA 201H
+        JMP 0
+", scratch("cpm/input"), &[]);
+  cpm.input = Box::new(&b"xHELLO, WORLD\n"[..]);
+  cpm.run();
+  // Function 1 echoes, line input does not and stops at the buffer size
+  assert_eq!(cpm.console.as_deref(), Some(&b"xO"[..]));
+  assert_eq!(cpm.cpu.mem.read(0x0200), b'x');
+  let line: Vec<u8> = (0..10).map(|i| cpm.cpu.mem.read(0x0300 + i)).collect();
+  assert_eq!(line, b"\x08\x08HELLO, W");
+  assert_eq!(cpm.cpu.mem.read(0x0201), b'O');
+}
+
+#[test]
+fn two_runners_in_one_process() {
+  let first = cpm("
+        ORG 100H
+        JMP 0
+", scratch("cpm/first"), &[]);
+  let mut second = cpm("
+        ORG 100H
+        MVI C,1
+        CALL 5
+        JMP 0
+", scratch("cpm/second"), &[]);
+  second.input = Box::new(&b"!"[..]);
+  second.run();
+  assert_eq!(second.console.as_deref(), Some(&b"!"[..]));
+  drop(first);
+}
+
+#[test]
+fn print_string_stops_without_a_terminator() {
+  let mut cpm = cpm("
+        ORG 100H
+        MVI C,9
+        LXI D,200H
+        CALL 5
+        JMP 0
+", scratch("cpm/unterminated"), &[]);
+  assert!((0..=0xFFFF).all(|hblb| cpm.cpu.mem.read(hblb) != b'$'));
+  cpm.run();
+  assert_eq!(cpm.console.as_ref().map(Vec::len), Some(0x10000));
+}
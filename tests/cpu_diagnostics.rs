@@ -0,0 +1,106 @@
+//! Runs the classic 8080 exerciser programs against `Processor`.
+//!
+//! The `.COM` files are not distributed with the crate: copy TST8080.COM,
+//! 8080PRE.COM, CPUTEST.COM and 8080EXM.COM into `tests/roms`. The two
+//! quick ones run with every `cargo test` when present and are skipped with
+//! a note otherwise. CPUTEST and 8080EXM are slow and ignored, run them
+//! with `cargo test --release -- --ignored`, where a missing program fails.
+
+use std::fs;
+use std::path::Path;
+
+use invaders::bus::{Bus, NullIo};
+use invaders::processor::{HaltPolicy, Processor};
+
+const WBOOT: u16 = 0x0000;
+const BDOS: u16 = 0x0005;
+const TPA: u16 = 0x0100;
+const STACK: u16 = 0xFE00;
+
+/// Load a program at the start of the TPA and run it until it jumps to the
+/// warm boot vector, returning everything printed through the BDOS, or None
+/// if the program is not in `tests/roms`.
+fn run(name: &str, max_instructions: u64) -> Option<String> {
+  let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/roms").join(name);
+  let program = match fs::read(&path) {
+    Ok(program) => program,
+    Err(e) => {
+      eprintln!("{}: {}, copy {} into tests/roms", path.display(), e, name);
+      return None;
+    },
+  };
+
+  let mut cpu = Processor::flat();
+  for (i, &db) in program.iter().enumerate() {
+    cpu.mem.write(TPA + i as u16, db);
+  }
+  // BDOS entry is a bare RET; the word after it is read as the top of the TPA
+  cpu.mem.write(BDOS, 0xC9);
+  cpu.mem.write(BDOS + 1, STACK as u8);
+  cpu.mem.write(BDOS + 2, (STACK >> 8) as u8);
+
+  let mut state = cpu.state();
+  state.pc = TPA;
+  state.sp = STACK - 2;
+  cpu.set_state(&state);
+  cpu.halt_policy = HaltPolicy::Stop;
+
+  let mut output = String::new();
+  for _ in 0..max_instructions {
+    match cpu.state().pc {
+      WBOOT => return Some(output),
+      BDOS => bdos(&cpu, &mut output),
+      _ => (),
+    }
+    cpu.exec(&mut NullIo);
+    assert!(!cpu.is_stopped(), "{} halted at {:04X}:\n{}", name, cpu.state().pc, output);
+  }
+  panic!("{} did not finish within {} instructions:\n{}", name, max_instructions, output);
+}
+
+/// Console output functions of the CP/M BDOS
+fn bdos(cpu: &Processor, output: &mut String) {
+  let state = cpu.state();
+  match state.c {
+    2 => output.push(state.e as char),
+    9 => {
+      let mut hblb = state.de();
+      loop {
+        let db = cpu.mem.read(hblb);
+        if db == b'$' {
+          break;
+        }
+        output.push(db as char);
+        hblb = hblb.wrapping_add(1);
+      }
+    },
+    _ => (),
+  }
+}
+
+#[test]
+fn tst8080() {
+  let Some(output) = run("TST8080.COM", 10_000_000) else { return };
+  assert!(output.contains("CPU IS OPERATIONAL"), "{}", output);
+}
+
+#[test]
+fn preliminary() {
+  let Some(output) = run("8080PRE.COM", 10_000_000) else { return };
+  assert!(output.contains("8080 Preliminary tests complete"), "{}", output);
+}
+
+#[test]
+#[ignore = "slow, needs tests/roms/CPUTEST.COM"]
+fn cputest() {
+  let output = run("CPUTEST.COM", 1_000_000_000).expect("CPUTEST.COM missing");
+  assert!(output.contains("CPU TESTS OK"), "{}", output);
+}
+
+#[test]
+#[ignore = "needs tests/roms/8080EXM.COM, takes several billion instructions"]
+fn exerciser() {
+  let output = run("8080EXM.COM", 100_000_000_000).expect("8080EXM.COM missing");
+  assert!(!output.contains("ERROR"), "{}", output);
+  assert!(output.contains("Tests complete"), "{}", output);
+}
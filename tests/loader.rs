@@ -0,0 +1,111 @@
+//! Checks loading Intel HEX and raw binaries into memory.
+
+mod common;
+
+use std::{fs, io};
+
+use invaders::asm::assemble;
+use invaders::bus::{Bus, NullIo};
+use invaders::loader::{Binary, Segment};
+use invaders::memory::{Memory, ROM_SIZE};
+use invaders::processor::{HaltPolicy, Processor};
+
+use common::scratch;
+
+/// Intel HEX record with its checksum
+fn record(kind: u8, address: u16, data: &[u8]) -> String {
+  let mut bytes = vec![data.len() as u8, (address >> 8) as u8, address as u8, kind];
+  bytes.extend(data);
+  let sum = bytes.iter().fold(0u8, |sum, &db| sum.wrapping_add(db));
+  bytes.push(sum.wrapping_neg());
+  format!(":{}\n", bytes.iter().map(|db| format!("{:02X}", db)).collect::<String>())
+}
+
+#[test]
+fn intel_hex_records() {
+  let text = [
+    record(0x00, 0x0100, &[0x3E, 0x42]),
+    record(0x00, 0x0102, &[0x76]),
+    record(0x02, 0x0000, &[0x01, 0x00]),
+    record(0x00, 0x0000, &[0xAA, 0xBB]),
+    record(0x04, 0x0000, &[0x00, 0x00]),
+    record(0x05, 0x0000, &[0x00, 0x00, 0x01, 0x00]),
+    record(0x01, 0x0000, &[]),
+    String::from(":garbage after the end\n"),
+  ].concat();
+  let binary = Binary::parse_hex(&text).unwrap();
+  assert_eq!(binary.segments, [
+    Segment { address: 0x0100, bytes: vec![0x3E, 0x42, 0x76] },
+    Segment { address: 0x1000, bytes: vec![0xAA, 0xBB] },
+  ]);
+  assert_eq!(binary.entry, Some(0x0100));
+  assert_eq!(binary.len(), 5);
+
+  let segment_entry = record(0x03, 0x0000, &[0x00, 0x0F, 0x00, 0x10]);
+  assert_eq!(Binary::parse_hex(&segment_entry).unwrap().entry, Some(0x0100));
+}
+
+#[test]
+fn intel_hex_errors() {
+  let mut bad_sum = record(0x00, 0x0000, &[0x01]);
+  bad_sum.replace_range(9..11, "02");
+  assert_eq!(Binary::parse_hex(&bad_sum).unwrap_err(), "line 1: bad checksum");
+  let text = record(0x00, 0x0000, &[0x00]) + "0000000000\n";
+  assert_eq!(Binary::parse_hex(&text).unwrap_err(), "line 2: expected ':'");
+  let text = record(0x04, 0x0000, &[0x00, 0x01]) + &record(0x00, 0x0000, &[0x00]);
+  assert_eq!(Binary::parse_hex(&text).unwrap_err(), "line 2: data beyond 64 KiB");
+  let text = record(0x00, 0xFFFF, &[0x00, 0x00]);
+  assert_eq!(Binary::parse_hex(&text).unwrap_err(), "line 1: data beyond 64 KiB");
+  let text = record(0x04, 0x0000, &[0xFF, 0xFF]) + &record(0x00, 0xFFFF, &[0x00, 0x00]);
+  assert_eq!(Binary::parse_hex(&text).unwrap_err(), "line 2: data beyond 64 KiB");
+  assert_eq!(Binary::parse_hex(":0100000000\n").unwrap_err(), "line 1: record length does not match its data");
+  assert_eq!(Binary::parse_hex(&record(0x06, 0x0000, &[])).unwrap_err(), "line 1: unknown record type 06");
+  assert_eq!(Binary::parse_hex(":0000001FF\u{e9}0").unwrap_err(), "line 1: invalid hex digit");
+}
+
+#[test]
+fn raw_binary_runs_from_its_entry_point() {
+  let program = assemble("
+        ORG 8000H
+START:  LXI SP,9000H
+        MVI A,7
+        CALL DOUBLE
+        STA 9000H
+        HLT
+DOUBLE: ADD A
+        RET
+").unwrap();
+  let mut binary = Binary::raw(program.origin, program.bytes);
+  binary.entry = Some(program.symbols["START"]);
+  let mut cpu = Processor::flat();
+  cpu.halt_policy = HaltPolicy::Stop;
+  cpu.load(&binary);
+  assert_eq!(cpu.state().pc, 0x8000);
+  while !cpu.is_stopped() {
+    cpu.exec(&mut NullIo);
+  }
+  assert_eq!(cpu.mem.read(0x9000), 14);
+}
+
+#[test]
+fn loading_fills_rom() {
+  let mut cpu = Processor::new(Memory::new([0x0; ROM_SIZE]));
+  cpu.load(&Binary::raw(0x1FFE, vec![0x11, 0x22, 0x33, 0x44]));
+  assert_eq!(cpu.state().pc, 0);
+  assert_eq!(cpu.mem.read(0x1FFE), 0x11);
+  assert_eq!(cpu.mem.read(0x1FFF), 0x22);
+  assert_eq!(cpu.mem.read(0x2000), 0x33);
+  assert_eq!(cpu.mem.read(0x4001), 0x44);
+  cpu.mem.write(0x1FFE, 0x00);
+  assert_eq!(cpu.mem.read(0x1FFE), 0x11);
+}
+
+#[test]
+fn raw_binary_must_end_below_64_kib() {
+  let path = scratch("loader").join("raw.bin");
+  fs::write(&path, [0x00; 0x20]).unwrap();
+  assert_eq!(Binary::load(&path, 0xFFE0).unwrap().len(), 0x20);
+  let e = Binary::load(&path, 0xFFE1).unwrap_err();
+  assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+  assert_eq!(e.to_string(), "data beyond 64 KiB");
+}
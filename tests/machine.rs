@@ -0,0 +1,41 @@
+//! Checks the Space Invaders machine around its I/O ports.
+
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+
+use invaders::asm::assemble;
+use invaders::loader::Binary;
+use invaders::machine::Machine;
+use invaders::memory::{ROM_SIZE, VIDEO_SIZE};
+use invaders::sound::{Sample, SoundEvent};
+
+#[test]
+fn reset_clears_the_shifter_and_latches() {
+  let program = assemble("
+        IN 3
+        OUT 5
+        MVI A,0FFH
+        OUT 4
+        OUT 4
+        MVI A,21H
+        OUT 3
+        HLT
+").unwrap();
+  let mut machine = Machine::new([0x0; ROM_SIZE], Arc::new(Mutex::new([0x0; VIDEO_SIZE])));
+  machine.load(&Binary::raw(program.origin, program.bytes));
+  let (tx, rx) = mpsc::channel();
+  machine.sound(tx);
+  let run = |machine: &mut Machine| {
+    for _ in 0..10 {
+      machine.exec();
+    }
+  };
+
+  run(&mut machine);
+  assert_eq!(rx.try_iter().collect::<Vec<_>>(), [SoundEvent::Amplifier(true), SoundEvent::Start(Sample::Ufo)]);
+  machine.reset();
+  assert_eq!(rx.try_iter().collect::<Vec<_>>(), [SoundEvent::Amplifier(false), SoundEvent::Stop(Sample::Ufo)]);
+  // The shifter reads 0 again and the UFO starts over
+  run(&mut machine);
+  assert_eq!(rx.try_iter().collect::<Vec<_>>(), [SoundEvent::Amplifier(true), SoundEvent::Start(Sample::Ufo)]);
+}
@@ -0,0 +1,68 @@
+//! Checks the memory map regions.
+
+use std::sync::{Arc, Mutex};
+
+use invaders::bus::Bus;
+use invaders::memory::{Fault, Handler, Memory, MemoryMap, OPEN_BUS, ROM_SIZE};
+
+#[test]
+fn space_invaders_ram_mirror() {
+  let mut rom = [0; ROM_SIZE];
+  rom[0x10] = 0xC3;
+  let mut mem = Memory::new(rom);
+  mem.write(0x2400, 0x5A);
+  assert_eq!(mem.read(0x4400), 0x5A);
+  assert_eq!(mem.read(0xE400), 0x5A);
+  mem.write(0xFFFF, 0xA5);
+  assert_eq!(mem.read(0x3FFF), 0xA5);
+  assert_eq!(mem.read(0x0010), 0xC3);
+  mem.write(0x0010, 0x00);
+  assert_eq!(mem.read(0x0010), 0xC3);
+}
+
+#[test]
+fn log_records_rom_writes_and_unmapped_reads() {
+  let mut mem = Memory::from_map(MemoryMap::new().rom(0x0000, 0x0FFF, vec![0; 0x1000]).ram(0x2000, 0x23FF));
+  mem.write(0x0100, 0x12);
+  assert_eq!(mem.read(0x8000), OPEN_BUS);
+  assert!(mem.take_log().is_empty());
+
+  mem.set_logging(true);
+  mem.write(0x0100, 0x12);
+  assert_eq!(mem.read(0x8000), OPEN_BUS);
+  mem.write(0x2400, 0x34);
+  mem.write(0x2000, 0x56);
+  assert_eq!(mem.take_log(), [
+    Fault::RomWrite { hblb: 0x0100, db: 0x12 },
+    Fault::UnmappedRead { hblb: 0x8000 },
+    Fault::UnmappedWrite { hblb: 0x2400, db: 0x34 },
+  ]);
+  assert!(mem.take_log().is_empty());
+}
+
+struct Latch(Arc<Mutex<Vec<(u16, u8)>>>);
+
+impl Handler for Latch {
+  fn read(&self, offset: u16) -> u8 {
+    offset as u8 | 0x80
+  }
+
+  fn write(&mut self, offset: u16, db: u8) {
+    self.0.lock().unwrap().push((offset, db));
+  }
+}
+
+#[test]
+fn masked_callback_and_open_bus() {
+  let writes = Arc::new(Mutex::new(Vec::new()));
+  let mut mem = Memory::from_map(MemoryMap::new()
+    .callback(0x6000, 0x6FFF, Box::new(Latch(Arc::clone(&writes)))).mask(0x0003)
+    .open_bus(0x7000, 0x7FFF, 0x00)
+    .ram(0x0000, 0xFFFF).mask(0x00FF));
+  assert_eq!(mem.read(0x6005), 0x81);
+  mem.write(0x6FFE, 0x42);
+  assert_eq!(*writes.lock().unwrap(), [(0x0002, 0x42)]);
+  assert_eq!(mem.read(0x7123), 0x00);
+  mem.write(0x0010, 0x99);
+  assert_eq!(mem.read(0x1210), 0x99);
+}
@@ -0,0 +1,143 @@
+//! Checks applying IPS and BPS patches to the ROM.
+
+mod common;
+
+use invaders::patch::{self, PatchError};
+
+use common::rom;
+
+/// BPS variable-length number
+fn number(out: &mut Vec<u8>, mut n: usize) {
+  loop {
+    let db = (n & 0x7F) as u8;
+    n >>= 7;
+    if n == 0 {
+      out.push(0x80 | db);
+      return;
+    }
+    out.push(db);
+    n -= 1;
+  }
+}
+
+/// BPS patch from its actions, with the checksums filled in
+fn bps(source: &[u8], target: &[u8], actions: &[u8]) -> Vec<u8> {
+  let mut out = b"BPS1".to_vec();
+  number(&mut out, source.len());
+  number(&mut out, target.len());
+  number(&mut out, 3);
+  out.extend(b"hi!");
+  out.extend(actions);
+  out.extend(crc32fast::hash(source).to_le_bytes());
+  out.extend(crc32fast::hash(target).to_le_bytes());
+  out.extend(crc32fast::hash(&out).to_le_bytes());
+  out
+}
+
+fn action(out: &mut Vec<u8>, kind: usize, len: usize) {
+  number(out, (len - 1) << 2 | kind);
+}
+
+#[test]
+fn ips_records() {
+  let mut rom = rom();
+  let original = rom.clone();
+  let mut ips = b"PATCH".to_vec();
+  ips.extend([0x00, 0x00, 0x10, 0x00, 0x02, 0xAA, 0xBB]);
+  ips.extend([0x00, 0x1F, 0xFE, 0x00, 0x00, 0x00, 0x02, 0xCC]);
+  ips.extend(b"EOF");
+  patch::apply(&mut rom, &ips).unwrap();
+  assert_eq!(rom[0x10..0x12], [0xAA, 0xBB]);
+  assert_eq!(rom[0x1FFE..], [0xCC, 0xCC]);
+  assert_eq!(rom[0x12..0x1FFE], original[0x12..0x1FFE]);
+
+  let mut growing = b"PATCH".to_vec();
+  growing.extend([0x00, 0x20, 0x00, 0x00, 0x01, 0x00]);
+  growing.extend(b"EOF");
+  assert_eq!(patch::apply(&mut rom, &growing), Err(PatchError::Size { size: 0x2001, expected: 0x2000 }));
+  // With a truncation length after EOF the image stays the same size
+  growing.extend([0x00, 0x20, 0x00]);
+  patch::apply(&mut rom, &growing).unwrap();
+
+  assert_eq!(patch::apply(&mut rom, b"PATCH\x00\x00\x10\x00\x05\x01"), Err(PatchError::Format(String::from("patch ends early"))));
+  assert_eq!(patch::apply(&mut rom, b"NOPE"), Err(PatchError::Format(String::from("not an IPS or BPS patch"))));
+}
+
+#[test]
+fn bps_actions() {
+  let source = rom();
+  let mut target = source.clone();
+  target[0x100..0x104].copy_from_slice(b"1UP!");
+  target.copy_within(0x800..0x810, 0x1000);
+  target[0x1010..0x1018].fill(0x55);
+
+  let mut actions = Vec::new();
+  action(&mut actions, 0, 0x100); // source read
+  action(&mut actions, 1, 4); // target read
+  actions.extend(b"1UP!");
+  action(&mut actions, 0, 0x1000 - 0x104);
+  action(&mut actions, 2, 0x10); // source copy from 0x800
+  number(&mut actions, 0x800 << 1);
+  action(&mut actions, 1, 1);
+  actions.push(0x55);
+  action(&mut actions, 3, 7); // overlapping target copy of the 0x55
+  number(&mut actions, 0x1010 << 1);
+  action(&mut actions, 2, 0x2000 - 0x1018); // source copy forward to 0x1018
+  number(&mut actions, (0x1018 - 0x810) << 1);
+  let patch = bps(&source, &target, &actions);
+
+  let mut rom = source.clone();
+  patch::apply(&mut rom, &patch).unwrap();
+  assert_eq!(rom, target);
+
+  // Patching again fails, the ROM is no longer the one the patch is for
+  let crc32 = crc32fast::hash(&rom);
+  assert_eq!(patch::apply(&mut rom, &patch), Err(PatchError::SourceChecksum { crc32, expected: crc32fast::hash(&source) }));
+}
+
+#[test]
+fn bps_checksums() {
+  let source = rom();
+  let mut actions = Vec::new();
+  action(&mut actions, 0, source.len());
+  let mut patch = bps(&source, &source, &actions);
+  let mut rom = source.clone();
+  patch::apply(&mut rom, &patch).unwrap();
+
+  let mut damaged = patch.clone();
+  damaged[8] ^= 0x01;
+  assert!(matches!(patch::apply(&mut rom, &damaged), Err(PatchError::PatchChecksum { .. })));
+
+  // Target checksum mismatch, with the patch checksum made to match
+  let len = patch.len();
+  patch[len - 8] ^= 0x01;
+  let crc32 = crc32fast::hash(&patch[..len - 4]);
+  patch[len - 4..].copy_from_slice(&crc32.to_le_bytes());
+  assert!(matches!(patch::apply(&mut rom, &patch), Err(PatchError::TargetChecksum { .. })));
+  assert_eq!(rom, source);
+}
+
+#[test]
+fn bps_sizes_are_checked_before_use() {
+  let source = rom();
+  // Target size far beyond memory, with valid checksums
+  let mut patch = b"BPS1".to_vec();
+  number(&mut patch, source.len());
+  number(&mut patch, 0x1101_2345_6789_ABCD);
+  number(&mut patch, 0);
+  patch.extend(crc32fast::hash(&source).to_le_bytes());
+  patch.extend(0u32.to_le_bytes());
+  patch.extend(crc32fast::hash(&patch).to_le_bytes());
+  let mut rom = source.clone();
+  assert_eq!(patch::apply(&mut rom, &patch), Err(PatchError::Size { size: 0x1101_2345_6789_ABCD, expected: 0x2000 }));
+
+  // Target copy far longer than the target
+  let mut actions = Vec::new();
+  action(&mut actions, 1, 1);
+  actions.push(0x00);
+  action(&mut actions, 3, 0x3FFF_FFFF_FFFF);
+  number(&mut actions, 0);
+  let patch = bps(&source, &source, &actions);
+  assert_eq!(patch::apply(&mut rom, &patch), Err(PatchError::Format(String::from("BPS actions write past the target size"))));
+  assert_eq!(rom, source);
+}
@@ -0,0 +1,341 @@
+//! Processor flags, state snapshots, interrupts, HLT and cycle counts.
+
+mod common;
+
+use invaders::bus::{Bus, NullIo};
+use invaders::processor::{CpuState, Flags, HaltPolicy, Processor, HALT_CYCLES};
+
+use common::load;
+
+#[test]
+fn snapshot_round_trips() {
+  let mut cpu = load("
+        LXI B,3456H
+        LXI D,789AH
+        LXI H,0BCDEH
+        LXI SP,0FE00H
+        MVI A,12H
+        EI
+        HLT
+");
+  for _ in 0..7 {
+    cpu.exec(&mut NullIo);
+  }
+  let state = cpu.state();
+  assert_eq!((state.a, state.bc(), state.de(), state.hl()), (0x12, 0x3456, 0x789A, 0xBCDE));
+  assert_eq!((state.sp, state.pc), (0xFE00, 0x0010));
+  assert!(state.ie && state.halted);
+  assert_eq!(state.cycles, 10 * 4 + 7 + 4 + 7);
+
+  let mut other = Processor::flat();
+  other.set_state(&state);
+  assert_eq!(other.state(), state);
+}
+
+#[test]
+fn snapshot_display() {
+  let mut state = CpuState { a: 0x12, sp: 0xFE00, pc: 0x0100, ie: true, cycles: 1234, ..CpuState::default() };
+  state.set_bc(0x3456);
+  state.set_de(0x789A);
+  state.set_hl(0xBCDE);
+  state.flags = Flags { s: true, z: true, p: true, ..Flags::default() };
+  assert_eq!(state.to_string(), "PC=0100 SP=FE00 A=12 BC=3456 DE=789A HL=BCDE F=SZ-P- IE=1 HLT=0 CYC=1234");
+
+  state.set_psw(0x0001);
+  state.halted = true;
+  assert_eq!(state.to_string(), "PC=0100 SP=FE00 A=00 BC=3456 DE=789A HL=BCDE F=----C IE=1 HLT=1 CYC=1234");
+}
+
+#[test]
+fn one_instruction_runs_after_ei() {
+  let mut cpu = load("
+        LXI SP,100H
+        EI
+        NOP
+        NOP
+        HLT
+        ORG 8
+        HLT
+");
+  cpu.exec(&mut NullIo);
+  cpu.rst(1);
+  cpu.exec(&mut NullIo);
+  assert!(cpu.state().ie);
+  assert_eq!(cpu.state().pc, 0x0004);
+  cpu.exec(&mut NullIo);
+  assert_eq!(cpu.state().pc, 0x0005);
+  assert_eq!(cpu.exec(&mut NullIo), 11);
+  assert_eq!(cpu.state().pc, 0x0008);
+  assert_eq!((cpu.mem.read(0x00FF), cpu.mem.read(0x00FE)), (0x00, 0x05));
+}
+
+#[test]
+fn accepting_an_interrupt_disables_interrupts() {
+  let mut cpu = load("
+        LXI SP,100H
+        EI
+        NOP
+        HLT
+        ORG 10H
+        NOP
+        RET
+");
+  for _ in 0..3 {
+    cpu.exec(&mut NullIo);
+  }
+  cpu.rst(2);
+  cpu.exec(&mut NullIo);
+  let state = cpu.state();
+  assert_eq!(state.pc, 0x0010);
+  assert!(!state.ie);
+  // A second request is not taken inside the handler
+  cpu.rst(2);
+  cpu.exec(&mut NullIo);
+  cpu.exec(&mut NullIo);
+  assert_eq!(cpu.state().pc, 0x0005);
+}
+
+#[test]
+fn hlt_wakes_on_an_interrupt() {
+  let mut cpu = load("
+        LXI SP,100H
+        EI
+        HLT
+        NOP
+        ORG 10H
+        HLT
+");
+  for _ in 0..3 {
+    cpu.exec(&mut NullIo);
+  }
+  for _ in 0..3 {
+    cpu.exec(&mut NullIo);
+    assert!(cpu.state().halted);
+    assert_eq!(cpu.state().pc, 0x0005);
+  }
+  cpu.rst(2);
+  cpu.exec(&mut NullIo);
+  let state = cpu.state();
+  assert!(!state.halted);
+  assert_eq!(state.pc, 0x0010);
+  // Returns past the HLT
+  assert_eq!((cpu.mem.read(0x00FF), cpu.mem.read(0x00FE)), (0x00, 0x05));
+}
+
+#[test]
+fn request_stays_pending_while_disabled() {
+  let mut cpu = load("
+        LXI SP,100H
+        NOP
+        NOP
+        EI
+        NOP
+        NOP
+        ORG 8
+        HLT
+");
+  cpu.rst(1);
+  for pc in [0x0003, 0x0004, 0x0005, 0x0006, 0x0007] {
+    cpu.exec(&mut NullIo);
+    assert_eq!(cpu.state().pc, pc);
+  }
+  cpu.exec(&mut NullIo);
+  assert_eq!(cpu.state().pc, 0x0008);
+  assert_eq!((cpu.mem.read(0x00FF), cpu.mem.read(0x00FE)), (0x00, 0x07));
+}
+
+#[test]
+fn stop_policy_ends_on_di_hlt() {
+  let mut cpu = load("
+        DI
+        HLT
+");
+  cpu.halt_policy = HaltPolicy::Stop;
+  cpu.exec(&mut NullIo);
+  cpu.exec(&mut NullIo);
+  assert!(cpu.is_stopped());
+  let cycles = cpu.state().cycles;
+  assert_eq!(cpu.exec(&mut NullIo), 0);
+  assert_eq!(cpu.state().cycles, cycles);
+  assert_eq!(cpu.state().pc, 0x0002);
+}
+
+#[test]
+fn wait_policy_idles_on_di_hlt() {
+  let mut cpu = load("
+        DI
+        HLT
+");
+  cpu.exec(&mut NullIo);
+  cpu.exec(&mut NullIo);
+  assert!(!cpu.is_stopped());
+  for _ in 0..3 {
+    assert_eq!(cpu.exec(&mut NullIo), HALT_CYCLES);
+  }
+  let state = cpu.state();
+  assert!(state.halted);
+  assert_eq!(state.pc, 0x0002);
+  assert_eq!(state.cycles, 4 + 7 + 3 * HALT_CYCLES as u64);
+}
+
+#[test]
+fn stop_policy_still_wakes_with_interrupts_enabled() {
+  let mut cpu = load("
+        LXI SP,100H
+        EI
+        HLT
+        ORG 18H
+        HLT
+");
+  cpu.halt_policy = HaltPolicy::Stop;
+  for _ in 0..3 {
+    cpu.exec(&mut NullIo);
+  }
+  assert!(!cpu.is_stopped());
+  assert_eq!(cpu.exec(&mut NullIo), HALT_CYCLES);
+  cpu.rst(3);
+  assert_eq!(cpu.exec(&mut NullIo), 11);
+  let state = cpu.state();
+  assert!(!state.halted);
+  assert_eq!(state.pc, 0x0018);
+}
+
+#[test]
+fn conditional_branch_cycles() {
+  let mut cpu = load("
+        LXI SP,100H
+        XRA A
+        CNZ SUB
+        CZ SUB
+        JNZ 0
+        JZ DONE
+SUB:    RC
+        RZ
+DONE:   HLT
+");
+  // Datasheet states: CALL cc 11/17, RET cc 5/11, Jcc 10 either way
+  let expected = [10, 4, 11, 17, 5, 11, 10, 10, 7];
+  for &cycles in &expected {
+    assert_eq!(cpu.exec(&mut NullIo), cycles);
+  }
+  let state = cpu.state();
+  assert!(state.halted);
+  assert_eq!(state.cycles, expected.iter().map(|&cycles| cycles as u64).sum());
+}
+
+#[test]
+fn cycle_counter_does_not_wrap_at_32_bits() {
+  let mut cpu = load("
+        NOP
+        HLT
+");
+  let mut state = cpu.state();
+  state.cycles = u32::MAX as u64 - 1;
+  cpu.set_state(&state);
+  cpu.exec(&mut NullIo);
+  assert_eq!(cpu.state().cycles, u32::MAX as u64 + 3);
+}
+
+/// Run `source` from 0 until HLT and return the final state
+fn run(source: &str) -> CpuState {
+  let mut cpu = load(source);
+  while !cpu.state().halted {
+    cpu.exec(&mut NullIo);
+  }
+  cpu.state()
+}
+
+#[test]
+fn inr_and_dcr_auxiliary_carry() {
+  let state = run("
+        STC
+        MVI A,0FH
+        INR A
+        HLT
+");
+  assert_eq!(state.a, 0x10);
+  assert!(state.flags.ac);
+  // INR and DCR leave CY alone
+  assert!(state.flags.cy);
+  assert!(!run("MVI A,0EH\nINR A\nHLT").flags.ac);
+
+  // Set unless the low nibble borrows
+  let state = run("
+        MVI A,10H
+        DCR A
+        HLT
+");
+  assert_eq!(state.a, 0x0F);
+  assert!(!state.flags.ac && !state.flags.cy);
+  assert!(run("MVI A,11H\nDCR A\nHLT").flags.ac);
+}
+
+#[test]
+fn subtract_auxiliary_carry() {
+  // AC is the carry out of bit 3 when adding the complement
+  let state = run("MVI A,12H\nSUI 01H\nHLT");
+  assert_eq!(state.a, 0x11);
+  assert!(state.flags.ac && !state.flags.cy);
+  let state = run("MVI A,10H\nSUI 01H\nHLT");
+  assert_eq!(state.a, 0x0F);
+  assert!(!state.flags.ac && !state.flags.cy);
+  let state = run("MVI A,00H\nSUI 01H\nHLT");
+  assert_eq!(state.a, 0xFF);
+  assert!(!state.flags.ac && state.flags.cy);
+  let state = run("STC\nMVI A,12H\nSBI 02H\nHLT");
+  assert_eq!(state.a, 0x0F);
+  assert!(!state.flags.ac && !state.flags.cy);
+  let state = run("MVI A,35H\nCPI 05H\nHLT");
+  assert_eq!(state.a, 0x35);
+  assert!(state.flags.ac && !state.flags.cy);
+}
+
+#[test]
+fn logical_auxiliary_carry() {
+  // ANA sets AC from bit 3 of either operand, ORA and XRA clear it
+  assert!(run("MVI A,08H\nANI 00H\nHLT").flags.ac);
+  assert!(run("MVI A,00H\nANI 08H\nHLT").flags.ac);
+  assert!(!run("MVI A,0F7H\nANI 0F7H\nHLT").flags.ac);
+  let state = run("STC\nMVI A,0FH\nADI 01H\nORI 08H\nHLT");
+  assert!(!state.flags.ac && !state.flags.cy);
+  let state = run("STC\nMVI A,0FH\nADI 01H\nXRI 08H\nHLT");
+  assert!(!state.flags.ac && !state.flags.cy);
+}
+
+#[test]
+fn parity_zero_and_sign() {
+  let state = run("MVI A,03H\nORA A\nHLT");
+  assert!(state.flags.p && !state.flags.z && !state.flags.s);
+  let state = run("MVI A,07H\nORA A\nHLT");
+  assert!(!state.flags.p);
+  let state = run("MVI A,81H\nORA A\nHLT");
+  assert!(state.flags.p && state.flags.s);
+  let state = run("XRA A\nHLT");
+  assert!(state.flags.p && state.flags.z);
+  let state = run("MVI A,0FEH\nINR A\nHLT");
+  assert!(state.flags.p && state.flags.s && !state.flags.z);
+}
+
+#[test]
+fn psw_layout() {
+  // Bit 1 always reads 1, bits 3 and 5 always 0
+  let state = run("
+        LXI SP,100H
+        LXI B,00FFH
+        PUSH B
+        POP PSW
+        PUSH PSW
+        POP D
+        LXI B,0
+        PUSH B
+        POP PSW
+        PUSH PSW
+        POP H
+        HLT
+");
+  assert_eq!(state.e, 0xD7);
+  assert_eq!(state.l, 0x02);
+  assert_eq!(Flags::from_psw(0xD7), Flags { s: true, z: true, ac: true, p: true, cy: true });
+  assert_eq!(Flags::from_psw(0xFF).to_psw(), 0xD7);
+  assert_eq!(Flags::default().to_psw(), 0x02);
+}
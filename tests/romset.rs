@@ -0,0 +1,115 @@
+//! Checks loading the ROM set from its different layouts.
+
+mod common;
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::slice;
+
+use invaders::romset::{self, RomError};
+
+use common::{rom, rom_path, scratch};
+
+/// Write the chips under the given names, in the order h, g, f, e
+fn write_chips(dir: &Path, names: [&str; 4]) -> Vec<PathBuf> {
+  let rom = rom();
+  names.iter().enumerate().map(|(i, name)| {
+    let path = dir.join(name);
+    fs::write(&path, &rom[i * 0x800..(i + 1) * 0x800]).unwrap();
+    path
+  }).collect()
+}
+
+const NAMES: [&str; 4] = ["invaders.h", "invaders.g", "invaders.f", "invaders.e"];
+
+#[test]
+fn combined_image() {
+  let path = rom_path();
+  let loaded = romset::load(&[path], true).unwrap();
+  assert_eq!(loaded.image[..], rom()[..]);
+  assert_eq!(loaded.set.name, "invaders");
+  assert!(loaded.warnings.is_empty());
+}
+
+#[test]
+fn directory_and_chip_files() {
+  let dir = scratch("romset/directory");
+  let paths = write_chips(&dir, NAMES);
+  assert_eq!(romset::load(&[dir], true).unwrap().image[..], rom()[..]);
+  let loaded = romset::load(&paths, true).unwrap();
+  assert_eq!(loaded.image[..], rom()[..]);
+  assert!(loaded.warnings.is_empty());
+}
+
+#[test]
+fn zip_archive() {
+  let path = scratch("romset/zip").join("invaders.zip");
+  let mut zip = zip::ZipWriter::new(fs::File::create(&path).unwrap());
+  let rom = rom();
+  for (i, name) in NAMES.iter().enumerate().rev() {
+    zip.start_file(*name, zip::write::FileOptions::default()).unwrap();
+    zip.write_all(&rom[i * 0x800..(i + 1) * 0x800]).unwrap();
+  }
+  zip.finish().unwrap();
+  assert_eq!(romset::load(&[path], true).unwrap().image[..], rom[..]);
+}
+
+#[test]
+fn misnamed_chips_are_found_by_checksum() {
+  let dir = scratch("romset/misnamed");
+  write_chips(&dir, ["invaders.h", "invaders.g", "invaders.e", "invaders.f"]);
+  let loaded = romset::load(&[dir], true).unwrap();
+  assert_eq!(loaded.image[..], rom()[..]);
+  assert_eq!(loaded.warnings, [
+    "invaders.f found as invaders.e",
+    "invaders.e found as invaders.f",
+  ]);
+
+  let dir = scratch("romset/renamed");
+  write_chips(&dir, ["a.bin", "invaders.g", "invaders.f", "invaders.e"]);
+  let loaded = romset::load(&[dir], true).unwrap();
+  assert_eq!(loaded.image[..], rom()[..]);
+  assert_eq!(loaded.warnings, ["invaders.h found as a.bin"]);
+
+  let dir = scratch("romset/replaced");
+  write_chips(&dir, ["invaders.h", "invaders.g", "invaders.f", "good.e"]);
+  fs::write(dir.join("invaders.e"), [0; 0x800]).unwrap();
+  let loaded = romset::load(&[dir], true).unwrap();
+  assert_eq!(loaded.image[..], rom()[..]);
+  assert_eq!(loaded.warnings, ["invaders.e is a bad dump, using good.e which matches it"]);
+}
+
+#[test]
+fn missing_chips() {
+  let dir = scratch("romset/missing");
+  write_chips(&dir, NAMES);
+  fs::remove_file(dir.join("invaders.g")).unwrap();
+  fs::remove_file(dir.join("invaders.e")).unwrap();
+  let error = romset::load(&[dir], true).err().unwrap();
+  assert!(matches!(error, RomError::Missing("invaders", ref chips) if chips[..] == ["invaders.g", "invaders.e"]));
+  assert_eq!(error.to_string(), "invaders: missing invaders.g, invaders.e");
+}
+
+#[test]
+fn bad_dumps() {
+  let dir = scratch("romset/bad");
+  write_chips(&dir, NAMES);
+  let mut bytes = fs::read(dir.join("invaders.f")).unwrap();
+  bytes[0x100] ^= 0x01;
+  fs::write(dir.join("invaders.f"), &bytes).unwrap();
+  let error = romset::load(slice::from_ref(&dir), true).err().unwrap();
+  assert!(matches!(error, RomError::BadDump { ref name, expected: 0x0ccead96, .. } if name == "invaders.f"));
+
+  // Unverified, the patched chip is loaded as it is
+  let loaded = romset::load(slice::from_ref(&dir), false).unwrap();
+  assert_eq!(loaded.image[0x1100], rom()[0x1100] ^ 0x01);
+
+  fs::write(dir.join("invaders.f"), &bytes[..0x400]).unwrap();
+  let error = romset::load(&[dir], false).err().unwrap();
+  assert!(matches!(error, RomError::Size { size: 0x400, expected: 0x800, .. }));
+
+  let path = scratch("romset/short").join("space-invaders.rom");
+  fs::write(&path, &rom()[..0x1000]).unwrap();
+  assert!(matches!(romset::load(&[path], true), Err(RomError::Size { size: 0x1000, expected: 0x2000, .. })));
+}
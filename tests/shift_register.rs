@@ -0,0 +1,55 @@
+//! Checks the Space Invaders shift register against the bits it should
+//! select, for every offset and pair of data bytes.
+
+use invaders::shift_register::ShiftRegister;
+
+#[test]
+fn every_offset_and_data_pattern() {
+  let mut shift = ShiftRegister::new();
+  for offset in 0..8 {
+    shift.write_offset(offset);
+    for hi in 0..=0xFF {
+      for lo in 0..=0xFF {
+        shift.write_data(lo);
+        shift.write_data(hi);
+        let value = u16::from_be_bytes([hi, lo]);
+        assert_eq!(shift.value(), value);
+        // Bit 7 of the result is bit 15 - offset of the register
+        let expected = (0..8).fold(0, |db, bit| db | ((value >> (8 - offset + bit) & 1) as u8) << bit);
+        assert_eq!(shift.read(), expected, "{:04X} offset {}", value, offset);
+      }
+    }
+  }
+}
+
+#[test]
+fn data_shifts_through_a_byte_at_a_time() {
+  let mut shift = ShiftRegister::new();
+  assert_eq!(shift.value(), 0x0000);
+  shift.write_data(0xAB);
+  assert_eq!(shift.value(), 0xAB00);
+  shift.write_data(0xCD);
+  assert_eq!(shift.value(), 0xCDAB);
+  shift.write_data(0xEF);
+  assert_eq!(shift.value(), 0xEFCD);
+  assert_eq!(shift.read(), 0xEF);
+  shift.write_offset(0x04);
+  assert_eq!(shift.read(), 0xFC);
+}
+
+#[test]
+fn offset_uses_the_low_three_bits() {
+  let mut shift = ShiftRegister::new();
+  shift.write_data(0x80);
+  shift.write_data(0x81);
+  for db in 0..=0xFF {
+    shift.write_offset(db);
+    assert_eq!(shift.offset(), db & 0x7);
+  }
+  shift.write_offset(0x00);
+  assert_eq!(shift.read(), 0x81);
+  shift.write_offset(0xFF);
+  assert_eq!(shift.read(), 0xC0);
+  shift.write_offset(0xF9);
+  assert_eq!(shift.read(), 0x03);
+}
@@ -0,0 +1,137 @@
+//! Checks decoding the sound ports, mixing and loading samples.
+
+mod common;
+
+use std::fs;
+use std::sync::mpsc;
+
+use invaders::sound::{self, Mixer, Sample, SoundEvent, SoundLatches};
+
+use common::scratch;
+
+#[test]
+fn latches_trigger_on_rising_edges() {
+  let mut latches = SoundLatches::new();
+  assert_eq!(latches.write(3, 0x20), [SoundEvent::Amplifier(true)]);
+  assert_eq!(latches.write(3, 0x23), [SoundEvent::Start(Sample::Ufo), SoundEvent::Start(Sample::Shot)]);
+  // Held bits do not restart their sounds
+  assert_eq!(latches.write(3, 0x23), []);
+  assert_eq!(latches.write(3, 0x2D), [SoundEvent::Start(Sample::PlayerDeath), SoundEvent::Start(Sample::InvaderDeath)]);
+  // Only the looping UFO stops when its bit clears
+  assert_eq!(latches.write(3, 0x20), [SoundEvent::Stop(Sample::Ufo)]);
+  assert_eq!(latches.write(3, 0x30), [SoundEvent::Start(Sample::ExtendedPlay)]);
+  assert_eq!(latches.write(3, 0x00), [SoundEvent::Amplifier(false)]);
+
+  let fleet = [Sample::Fleet1, Sample::Fleet2, Sample::Fleet3, Sample::Fleet4];
+  for (bit, sample) in fleet.into_iter().enumerate() {
+    assert_eq!(latches.write(5, 1 << bit), [SoundEvent::Start(sample)]);
+  }
+  assert_eq!(latches.write(5, 0x10), [SoundEvent::Start(Sample::UfoHit)]);
+  // Screen flip
+  assert_eq!(latches.write(5, 0x20), []);
+  assert_eq!(latches.write(4, 0xFF), []);
+}
+
+fn mixer(samples: &[(Sample, Vec<i16>)]) -> (Mixer, mpsc::Sender<SoundEvent>) {
+  let mut data = vec![None; Sample::ALL.len()];
+  for (sample, samples) in samples {
+    data[sample.index()] = Some(samples.clone());
+  }
+  let (tx, rx) = mpsc::channel();
+  (Mixer::new(data, rx), tx)
+}
+
+#[test]
+fn mixing() {
+  let (mut mixer, tx) = mixer(&[(Sample::Ufo, vec![1, 2, 3]), (Sample::Shot, vec![100, 200]), (Sample::UfoHit, vec![i16::MAX; 4])]);
+  let mut out = [0; 5];
+
+  // Nothing plays with the amplifier off
+  tx.send(SoundEvent::Start(Sample::Shot)).unwrap();
+  mixer.mix(&mut out);
+  assert_eq!(out, [0; 5]);
+
+  tx.send(SoundEvent::Amplifier(true)).unwrap();
+  tx.send(SoundEvent::Start(Sample::Ufo)).unwrap();
+  tx.send(SoundEvent::Start(Sample::Shot)).unwrap();
+  mixer.mix(&mut out);
+  assert_eq!(out, [101, 202, 3, 1, 2]);
+  assert_eq!(mixer.playing(), 1);
+  mixer.mix(&mut out);
+  assert_eq!(out, [3, 1, 2, 3, 1]);
+
+  tx.send(SoundEvent::Start(Sample::UfoHit)).unwrap();
+  tx.send(SoundEvent::Start(Sample::PlayerDeath)).unwrap();
+  mixer.mix(&mut out);
+  assert_eq!(out, [i16::MAX, i16::MAX, i16::MAX, i16::MAX, 3]);
+  assert_eq!(mixer.playing(), 1);
+
+  tx.send(SoundEvent::Stop(Sample::Ufo)).unwrap();
+  mixer.mix(&mut out);
+  assert_eq!(out, [0; 5]);
+
+  tx.send(SoundEvent::Start(Sample::Ufo)).unwrap();
+  tx.send(SoundEvent::Amplifier(false)).unwrap();
+  mixer.mix(&mut out);
+  assert_eq!(out, [0; 5]);
+  assert_eq!(mixer.playing(), 0);
+}
+
+#[test]
+fn loop_latched_with_the_amplifier_off() {
+  let (mut mixer, tx) = mixer(&[(Sample::Ufo, vec![1, 2, 3]), (Sample::Shot, vec![100, 200])]);
+  let mut out = [0; 4];
+
+  // The UFO bit set before the amplifier is on starts the loop with it,
+  // one-shot samples are gone
+  tx.send(SoundEvent::Start(Sample::Ufo)).unwrap();
+  tx.send(SoundEvent::Start(Sample::Shot)).unwrap();
+  mixer.mix(&mut out);
+  assert_eq!(out, [0; 4]);
+  tx.send(SoundEvent::Amplifier(true)).unwrap();
+  mixer.mix(&mut out);
+  assert_eq!(out, [1, 2, 3, 1]);
+
+  // Off and on again resumes the loop from the start
+  tx.send(SoundEvent::Amplifier(false)).unwrap();
+  tx.send(SoundEvent::Amplifier(true)).unwrap();
+  mixer.mix(&mut out);
+  assert_eq!(out, [1, 2, 3, 1]);
+
+  // Cleared while off, it stays silent
+  tx.send(SoundEvent::Amplifier(false)).unwrap();
+  tx.send(SoundEvent::Stop(Sample::Ufo)).unwrap();
+  tx.send(SoundEvent::Amplifier(true)).unwrap();
+  mixer.mix(&mut out);
+  assert_eq!(out, [0; 4]);
+  assert_eq!(mixer.playing(), 0);
+}
+
+#[test]
+fn loading_samples() {
+  let dir = scratch("samples");
+
+  let spec = hound::WavSpec { channels: 2, sample_rate: 22_050, bits_per_sample: 16, sample_format: hound::SampleFormat::Int };
+  let mut writer = hound::WavWriter::create(dir.join("0.wav"), spec).unwrap();
+  for s in [100, 300, -1000, -2000, 0, 0] {
+    writer.write_sample(s as i16).unwrap();
+  }
+  writer.finalize().unwrap();
+  let spec = hound::WavSpec { channels: 1, sample_rate: 44_100, bits_per_sample: 8, sample_format: hound::SampleFormat::Int };
+  let mut writer = hound::WavWriter::create(dir.join("1.wav"), spec).unwrap();
+  for s in [127, -128, 0] {
+    writer.write_sample(s as i8).unwrap();
+  }
+  writer.finalize().unwrap();
+  fs::write(dir.join("2.wav"), b"not a wav file").unwrap();
+
+  let (samples, warnings) = sound::load_samples(&dir, 44_100);
+  // Mixed to mono and resampled to twice the rate
+  assert_eq!(samples[0], Some(vec![200, -650, -1500, -750, 0, 0]));
+  assert_eq!(samples[1], Some(vec![127 << 8, -128 << 8, 0]));
+  assert_eq!(samples[2], None);
+  assert!(samples[3..].iter().all(Option::is_none));
+  // 9.wav is optional
+  assert_eq!(warnings.len(), 7);
+  assert!(warnings[0].contains("2.wav"));
+}
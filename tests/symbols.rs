@@ -0,0 +1,80 @@
+//! Checks the symbol file formats and the bundled Space Invaders map.
+
+mod common;
+
+use invaders::asm::assemble;
+use invaders::symbols::Symbols;
+use invaders::trace::{Image, Trace};
+
+fn rom() -> Image {
+  Image::new(0, common::rom())
+}
+
+#[test]
+fn simple_format() {
+  let symbols = Symbols::parse("
+; comment
+0x1400 DrawShiftedSprite ; shifted
+08FFH  DrawChar
+$2000  waitOnDraw
+").unwrap();
+  assert_eq!(symbols.len(), 3);
+  assert_eq!(symbols.name(0x1400), Some("DrawShiftedSprite"));
+  assert_eq!(symbols.comment(0x1400), Some("shifted"));
+  assert_eq!(symbols.comment(0x08FF), None);
+  assert_eq!(symbols.resolve("drawchar"), Some(0x08FF));
+  assert_eq!(symbols.resolve("DrawChar+0C"), Some(0x090B));
+  assert_eq!(symbols.resolve("18d4"), Some(0x18D4));
+  assert_eq!(symbols.resolve("Nowhere"), None);
+  assert!(Symbols::parse("1234 1bad").is_err());
+  assert!(Symbols::parse("1234 A\n5678 a").is_err());
+}
+
+#[test]
+fn sym_format() {
+  let symbols = Symbols::parse_sym("0000 WBOOT\t0005 BDOS\n0100 START\nBUFFER EQU 0200H\n").unwrap();
+  assert_eq!(symbols.address("BDOS"), Some(0x0005));
+  assert_eq!(symbols.address("START"), Some(0x0100));
+  assert_eq!(symbols.address("BUFFER"), Some(0x0200));
+  assert!(Symbols::parse_sym("0000 WBOOT 0005").is_err());
+}
+
+/// Every named routine is reached from the reset and interrupt vectors,
+/// except the object handlers the game calls through the table at 1B13
+/// and the sprite routine only they jump to
+#[test]
+fn space_invaders_routines_are_reached() {
+  let image = rom();
+  let symbols = Symbols::space_invaders();
+  let trace = Trace::new(&image, &Trace::vectors(0));
+  let unreached: Vec<&str> = symbols
+    .iter()
+    .filter(|&(hblb, _)| image.contains(hblb as usize) && !trace.instructions.contains_key(&hblb))
+    .map(|(_, symbol)| symbol.name.as_str())
+    .collect();
+  let indirect = ["PlayerTask", "PlayerShotTask", "RollingShotTask", "PlungerShotTask", "SaucerSquigglyTask", "DrawShiftedSprite"];
+  assert_eq!(unreached, indirect);
+}
+
+/// The game object tasks are the handlers in the object table copied to RAM
+#[test]
+fn space_invaders_game_objects() {
+  let image = rom();
+  let symbols = Symbols::space_invaders();
+  let handler = |i: usize| u16::from_le_bytes([image.bytes[0x1B13 + i * 16], image.bytes[0x1B14 + i * 16]]);
+  let tasks = ["PlayerTask", "PlayerShotTask", "RollingShotTask", "PlungerShotTask", "SaucerSquigglyTask"];
+  for (i, task) in tasks.iter().enumerate() {
+    assert_eq!(symbols.address(task), Some(handler(i)), "{}", task);
+  }
+}
+
+#[test]
+fn named_source_round_trip() {
+  let image = rom();
+  let symbols = Symbols::space_invaders();
+  let source = Trace::new(&image, &Trace::vectors(0)).source(&image, &symbols);
+  assert!(source.contains("\tCALL\tDrawChar\n"));
+  assert!(source.contains("waitOnDraw\tEQU\t2000H"));
+  let program = assemble(&source).unwrap();
+  assert!(program.bytes == image.bytes, "reassembled ROM differs");
+}
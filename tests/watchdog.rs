@@ -0,0 +1,62 @@
+//! Checks the watchdog and the report of ports nothing handles.
+
+use std::sync::{Arc, Mutex};
+
+use invaders::asm::assemble;
+use invaders::bus::PortAccess;
+use invaders::loader::Binary;
+use invaders::machine::Machine;
+use invaders::memory::{ROM_SIZE, VIDEO_SIZE};
+use invaders::watchdog::{Watchdog, TIMEOUT_FRAMES};
+
+#[test]
+fn times_out_without_kicks() {
+  let mut watchdog = Watchdog::default();
+  for _ in 0..TIMEOUT_FRAMES {
+    assert!(!watchdog.frame());
+  }
+  assert!(watchdog.frame());
+  assert_eq!(watchdog.frames(), 0);
+
+  let mut watchdog = Watchdog::new(3);
+  for _ in 0..10 {
+    assert!(!watchdog.frame());
+    assert!(!watchdog.frame());
+    watchdog.kick();
+  }
+  assert!(!watchdog.frame());
+  assert!(!watchdog.frame());
+  assert!(!watchdog.frame());
+  assert!(watchdog.frame());
+}
+
+#[test]
+fn unhandled_ports() {
+  let program = assemble("
+        ORG 0
+        IN 1
+        IN 3
+        IN 7
+        OUT 2
+        OUT 4
+        OUT 6
+        OUT 7
+        OUT 5
+        OUT 3
+        IN 7
+        JMP 0
+").unwrap();
+  let mut machine = Machine::new([0x0; ROM_SIZE], Arc::new(Mutex::new([0x0; VIDEO_SIZE])));
+  machine.load(&Binary::raw(program.origin, program.bytes));
+  for _ in 0..10 {
+    machine.exec();
+  }
+  assert!(machine.unhandled_ports().is_empty());
+
+  machine.log_ports(true);
+  for _ in 0..20 {
+    machine.exec();
+  }
+  assert_eq!(machine.unhandled_ports(), [PortAccess::In(7), PortAccess::Out(7)]);
+  assert_eq!(PortAccess::Out(7).to_string(), "OUT 07");
+}
@@ -0,0 +1,75 @@
+//! 16-bit wrap-around of PC, SP and HL on the flat 64 KiB profile.
+
+mod common;
+
+use invaders::bus::{Bus, NullIo};
+
+use common::load;
+
+#[test]
+fn pc_wraps_through_an_operand() {
+  let mut cpu = load("
+        ORG 0FFFEH
+        LXI H,1234H
+");
+  cpu.mem.write(0x0000, 0x12);
+  cpu.mem.write(0x0001, 0x76);
+  let mut state = cpu.state();
+  state.pc = 0xFFFE;
+  cpu.set_state(&state);
+  cpu.exec(&mut NullIo);
+  assert_eq!(cpu.state().hl(), 0x1234);
+  assert_eq!(cpu.state().pc, 0x0001);
+  cpu.exec(&mut NullIo);
+  assert!(cpu.state().halted);
+}
+
+#[test]
+fn sp_wraps_on_push_and_pop() {
+  let mut cpu = load("
+        LXI B,0ABCDH
+        PUSH B
+        POP D
+        HLT
+");
+  let mut state = cpu.state();
+  state.sp = 0x0001;
+  cpu.set_state(&state);
+  cpu.exec(&mut NullIo);
+  cpu.exec(&mut NullIo);
+  assert_eq!(cpu.state().sp, 0xFFFF);
+  assert_eq!(cpu.mem.read(0x0000), 0xAB);
+  assert_eq!(cpu.mem.read(0xFFFF), 0xCD);
+  cpu.exec(&mut NullIo);
+  assert_eq!(cpu.state().sp, 0x0001);
+  assert_eq!(cpu.state().de(), 0xABCD);
+}
+
+#[test]
+fn hl_wraps_on_memory_access() {
+  let mut cpu = load("
+        ORG 100H
+        LXI H,0FFFFH
+        MVI M,11H
+        INX H
+        MVI M,22H
+        LHLD 0FFFFH
+        SHLD 0FFFFH
+        LXI SP,0FFFFH
+        XTHL
+        HLT
+");
+  let mut state = cpu.state();
+  state.pc = 0x0100;
+  cpu.set_state(&state);
+  for _ in 0..5 {
+    cpu.exec(&mut NullIo);
+  }
+  assert_eq!(cpu.state().hl(), 0x2211);
+  for _ in 0..3 {
+    cpu.exec(&mut NullIo);
+  }
+  assert_eq!(cpu.state().hl(), 0x2211);
+  assert_eq!(cpu.mem.read(0xFFFF), 0x11);
+  assert_eq!(cpu.mem.read(0x0000), 0x22);
+}